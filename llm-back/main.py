@@ -92,6 +92,8 @@ class ChatRequest(BaseModel):
     messages: List[ChatMessage]
     max_new_tokens: Optional[int] = MAX_NEW_TOKENS
     temperature: Optional[float] = TEMPERATURE
+    # Промпт уже собран шаблоном в user-back и передаётся модели без изменений
+    raw_prompt: Optional[bool] = False
 
 class ChatResponse(BaseModel):
     response: str
@@ -114,7 +116,7 @@ async def chat_endpoint(request: ChatRequest):
 
     user_input = request.messages[0].content
 
-    prompt = f"[INST] {user_input} [/INST]"
+    prompt = user_input if request.raw_prompt else f"[INST] {user_input} [/INST]"
 
     try:
         inputs = tokenizer(prompt, return_tensors="pt").to(model.device)
@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS "usage_ledger";
+
+DROP TABLE IF EXISTS "user_plans";
+
+DROP TABLE IF EXISTS "plans";
@@ -0,0 +1,47 @@
+-- Add up migration script here
+
+CREATE TABLE
+    "plans" (
+        name VARCHAR(50) NOT NULL PRIMARY KEY,
+        chat_tokens_daily BIGINT,
+        chat_tokens_monthly BIGINT,
+        images_daily BIGINT,
+        images_monthly BIGINT,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW()
+    );
+
+INSERT INTO plans (name, chat_tokens_daily, chat_tokens_monthly, images_daily, images_monthly)
+VALUES
+    ('free', 20000, 300000, 5, 50),
+    ('pro', 200000, 4000000, 50, 1000),
+    ('unlimited', NULL, NULL, NULL, NULL);
+
+CREATE TABLE
+    "user_plans" (
+        user_id UUID NOT NULL PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+        plan VARCHAR(50) NOT NULL DEFAULT 'free' REFERENCES plans (name),
+        chat_token_credits BIGINT NOT NULL DEFAULT 0,
+        image_credits BIGINT NOT NULL DEFAULT 0,
+        updated_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW()
+    );
+
+CREATE TABLE
+    "usage_ledger" (
+        id BIGSERIAL PRIMARY KEY,
+        user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        kind VARCHAR(20) NOT NULL,
+        entry VARCHAR(20) NOT NULL,
+        amount BIGINT NOT NULL,
+        source VARCHAR(20),
+        note TEXT,
+        created_by UUID,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW()
+    );
+
+CREATE INDEX usage_ledger_user_created_idx ON usage_ledger (user_id, created_at DESC);
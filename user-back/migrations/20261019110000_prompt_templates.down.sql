@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS "conversation_messages";
+
+DROP TABLE IF EXISTS "conversations";
+
+DROP TABLE IF EXISTS "prompt_templates";
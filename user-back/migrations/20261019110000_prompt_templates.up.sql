@@ -0,0 +1,80 @@
+-- Add up migration script here
+
+CREATE TABLE
+    "prompt_templates" (
+        id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+        name VARCHAR(100) NOT NULL UNIQUE,
+        language VARCHAR(20) NOT NULL DEFAULT 'tatar',
+        persona VARCHAR(255) NOT NULL DEFAULT '',
+        system_prompt TEXT NOT NULL DEFAULT '',
+        prompt_format TEXT NOT NULL DEFAULT E'{history}[INST] {system}\n\n{message} [/INST]',
+        history_format TEXT NOT NULL DEFAULT '[INST] {user} [/INST] {assistant}</s>',
+        temperature REAL,
+        max_new_tokens INTEGER,
+        is_default BOOLEAN NOT NULL DEFAULT FALSE,
+        active BOOLEAN NOT NULL DEFAULT TRUE,
+        created_by UUID,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW(),
+            updated_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW()
+    );
+
+CREATE UNIQUE INDEX prompt_templates_default_language_idx ON prompt_templates (language)
+WHERE
+    is_default;
+
+INSERT INTO prompt_templates (name, language, persona, system_prompt, temperature, max_new_tokens, is_default)
+VALUES
+    (
+        'tatar-assistant',
+        'tatar',
+        'Татар AI-ярдәмчесе',
+        'Син — {persona}. Кулланучыга һәрвакыт татар телендә, кирилл язуында, ачык һәм ихтирамлы итеп җавап бир.',
+        0.7,
+        512,
+        TRUE
+    ),
+    (
+        'russian-assistant',
+        'russian',
+        'ИИ-помощник',
+        'Ты — {persona}. Отвечай пользователю на русском языке, понятно и вежливо.',
+        0.7,
+        512,
+        TRUE
+    );
+
+CREATE TABLE
+    "conversations" (
+        id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+        user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        template_id UUID REFERENCES prompt_templates (id) ON DELETE SET NULL,
+        title VARCHAR(255) NOT NULL DEFAULT '',
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW(),
+            updated_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW()
+    );
+
+CREATE INDEX conversations_user_updated_idx ON conversations (user_id, updated_at DESC);
+
+CREATE TABLE
+    "conversation_messages" (
+        id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+        conversation_id UUID NOT NULL REFERENCES conversations (id) ON DELETE CASCADE,
+        role VARCHAR(20) NOT NULL,
+        content TEXT NOT NULL,
+        template_id UUID REFERENCES prompt_templates (id) ON DELETE SET NULL,
+        -- clock_timestamp() keeps the user and assistant messages inserted in
+        -- one transaction ordered
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT clock_timestamp()
+    );
+
+CREATE INDEX conversation_messages_conversation_created_idx ON conversation_messages (conversation_id, created_at);
@@ -0,0 +1,19 @@
+-- Add up migration script here
+
+CREATE TABLE
+    "message_feedback" (
+        id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+        message_id UUID NOT NULL UNIQUE REFERENCES conversation_messages (id) ON DELETE CASCADE,
+        user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        rating VARCHAR(10) NOT NULL CHECK (rating IN ('up', 'down')),
+        correction TEXT,
+        consent BOOLEAN NOT NULL DEFAULT FALSE,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW(),
+            updated_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW()
+    );
+
+CREATE INDEX message_feedback_export_idx ON message_feedback (consent, created_at);
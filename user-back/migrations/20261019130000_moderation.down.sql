@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS "moderation_flags";
+
+DROP TABLE IF EXISTS "moderation_rules";
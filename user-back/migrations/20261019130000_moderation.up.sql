@@ -0,0 +1,46 @@
+-- Add up migration script here
+
+CREATE TABLE
+    "moderation_rules" (
+        id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+        pattern TEXT NOT NULL,
+        is_regex BOOLEAN NOT NULL DEFAULT FALSE,
+        language VARCHAR(20) NOT NULL DEFAULT 'any',
+        action VARCHAR(10) NOT NULL CHECK (action IN ('block', 'flag')),
+        active BOOLEAN NOT NULL DEFAULT TRUE,
+        created_by UUID,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW()
+    );
+
+INSERT INTO moderation_rules (pattern, is_regex, language, action)
+VALUES
+    ('child\s*porn\w*', TRUE, 'english', 'block'),
+    ('(naked|nude)\s+(child|kid|minor)\w*', TRUE, 'english', 'block'),
+    ('детск\w*\s+порн\w*', TRUE, 'russian', 'block'),
+    ('(голы\w*|обнаж[её]нн\w*)\s+(ребен\w*|ребён\w*|дет\w*)', TRUE, 'russian', 'block'),
+    ('бала\w*\s+порн\w*', TRUE, 'tatar', 'block'),
+    ('ялангач\s+бала\w*', TRUE, 'tatar', 'block');
+
+CREATE TABLE
+    "moderation_flags" (
+        id UUID NOT NULL PRIMARY KEY DEFAULT (uuid_generate_v4()),
+        user_id UUID REFERENCES users (id) ON DELETE SET NULL,
+        kind VARCHAR(20) NOT NULL,
+        content TEXT NOT NULL,
+        action VARCHAR(10) NOT NULL,
+        source VARCHAR(20) NOT NULL,
+        reason TEXT,
+        status VARCHAR(20) NOT NULL DEFAULT 'pending',
+        review_note TEXT,
+        reviewed_by UUID,
+        reviewed_at TIMESTAMP
+        WITH
+            TIME ZONE,
+            created_at TIMESTAMP
+        WITH
+            TIME ZONE DEFAULT NOW()
+    );
+
+CREATE INDEX moderation_flags_status_created_idx ON moderation_flags (status, created_at DESC);
@@ -0,0 +1,4 @@
+-- Add up migration script here
+
+ALTER TABLE "users"
+    ADD COLUMN locale VARCHAR(5) CHECK (locale IN ('tt', 'ru', 'en'));
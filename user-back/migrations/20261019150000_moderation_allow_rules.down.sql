@@ -0,0 +1,8 @@
+-- Add down migration script here
+
+DELETE FROM "moderation_rules" WHERE action = 'allow';
+
+ALTER TABLE "moderation_rules" DROP CONSTRAINT moderation_rules_action_check;
+
+ALTER TABLE "moderation_rules"
+    ADD CONSTRAINT moderation_rules_action_check CHECK (action IN ('block', 'flag'));
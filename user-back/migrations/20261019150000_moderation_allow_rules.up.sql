@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+ALTER TABLE "moderation_rules" DROP CONSTRAINT moderation_rules_action_check;
+
+ALTER TABLE "moderation_rules"
+    ADD CONSTRAINT moderation_rules_action_check CHECK (action IN ('allow', 'block', 'flag'));
@@ -0,0 +1,383 @@
+use crate::{
+    error::{ApiError, ErrorEnvelope},
+    gateway, jwt_auth,
+    model::{
+        Conversation, ConversationMessage, ConversationMessageSchema, CreateConversationSchema,
+        UpdateConversationSchema,
+    },
+    moderation::{self, ContentKind},
+    template::{self, GenerationParams},
+    AppState,
+};
+
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+use validator::Validate;
+
+const TITLE_MAX_CHARS: usize = 60;
+
+async fn find_conversation(
+    db: &Pool<Postgres>,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<Conversation>, sqlx::Error> {
+    sqlx::query_as!(
+        Conversation,
+        "SELECT id, user_id, template_id, title, created_at, updated_at FROM conversations WHERE id = $1 AND user_id = $2",
+        conversation_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Last `turns` completed `(user, assistant)` exchanges, oldest first.
+async fn load_history(
+    db: &Pool<Postgres>,
+    conversation_id: Uuid,
+    turns: i64,
+) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let mut messages = sqlx::query_as!(
+        ConversationMessage,
+        "SELECT id, conversation_id, role, content, template_id, created_at FROM conversation_messages WHERE conversation_id = $1 ORDER BY created_at DESC LIMIT $2",
+        conversation_id,
+        turns * 2
+    )
+    .fetch_all(db)
+    .await?;
+    messages.reverse();
+
+    let mut history = Vec::new();
+    let mut pending_user: Option<String> = None;
+    for message in messages {
+        match message.role.as_str() {
+            "user" => pending_user = Some(message.content),
+            "assistant" => {
+                if let Some(user) = pending_user.take() {
+                    history.push((user, message.content));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(history)
+}
+
+#[utoipa::path(
+    tag = "conversations",
+    responses(
+        (status = 201, description = "`data.conversation`: the created `Conversation`", body = Object),
+        (status = 400, description = "Unknown template", body = ErrorEnvelope),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 422, description = "Request validation failed", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[post("/conversations")]
+async fn create_conversation_handler(
+    body: web::Json<CreateConversationSchema>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    body.validate()?;
+
+    let prompt_template = match body.template_id {
+        Some(template_id) => Some(
+            template::find_active(&data.db, template_id)
+                .await?
+                .ok_or_else(|| ApiError::BadRequest("Unknown template".to_string()))?,
+        ),
+        None => {
+            template::find_default(
+                &data.db,
+                body.language
+                    .as_deref()
+                    .unwrap_or(template::DEFAULT_LANGUAGE),
+            )
+            .await?
+        }
+    };
+
+    let conversation = sqlx::query_as!(
+        Conversation,
+        "INSERT INTO conversations (user_id, template_id, title) VALUES ($1, $2, $3) RETURNING id, user_id, template_id, title, created_at, updated_at",
+        jwt.user.id,
+        prompt_template.map(|t| t.id),
+        body.title.as_deref().unwrap_or("").trim()
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "status": "success",
+        "data": {"conversation": conversation}
+    })))
+}
+
+#[utoipa::path(
+    tag = "conversations",
+    responses(
+        (status = 200, description = "`data.conversations`: the user's `Conversation`s", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[get("/conversations")]
+async fn list_conversations_handler(
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let conversations = sqlx::query_as!(
+        Conversation,
+        "SELECT id, user_id, template_id, title, created_at, updated_at FROM conversations WHERE user_id = $1 ORDER BY updated_at DESC LIMIT 100",
+        jwt.user.id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"conversations": conversations}
+    })))
+}
+
+#[utoipa::path(
+    tag = "conversations",
+    params(
+        ("id" = Uuid, Path, description = "Conversation id"),
+    ),
+    responses(
+        (status = 200, description = "`data.conversation` and its `data.messages`", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 404, description = "Conversation not found", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[get("/conversations/{id}")]
+async fn get_conversation_handler(
+    path: web::Path<Uuid>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let conversation = find_conversation(&data.db, path.into_inner(), jwt.user.id)
+        .await?
+        .ok_or(ApiError::NotFound("Conversation"))?;
+
+    let messages = sqlx::query_as!(
+        ConversationMessage,
+        "SELECT id, conversation_id, role, content, template_id, created_at FROM conversation_messages WHERE conversation_id = $1 ORDER BY created_at",
+        conversation.id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"conversation": conversation, "messages": messages}
+    })))
+}
+
+#[utoipa::path(
+    tag = "conversations",
+    params(
+        ("id" = Uuid, Path, description = "Conversation id"),
+    ),
+    responses(
+        (status = 200, description = "`data.conversation`: the updated `Conversation`", body = Object),
+        (status = 400, description = "Unknown template", body = ErrorEnvelope),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 404, description = "Conversation not found", body = ErrorEnvelope),
+        (status = 422, description = "Request validation failed", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[put("/conversations/{id}")]
+async fn update_conversation_handler(
+    path: web::Path<Uuid>,
+    body: web::Json<UpdateConversationSchema>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    body.validate()?;
+
+    if let Some(template_id) = body.template_id {
+        if template::find_active(&data.db, template_id)
+            .await?
+            .is_none()
+        {
+            return Err(ApiError::BadRequest("Unknown template".to_string()));
+        }
+    }
+
+    let conversation = sqlx::query_as!(
+        Conversation,
+        "UPDATE conversations SET
+            title = COALESCE($3, title),
+            template_id = COALESCE($4, template_id),
+            updated_at = NOW()
+         WHERE id = $1 AND user_id = $2
+         RETURNING id, user_id, template_id, title, created_at, updated_at",
+        path.into_inner(),
+        jwt.user.id,
+        body.title.as_deref().map(str::trim),
+        body.template_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or(ApiError::NotFound("Conversation"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"conversation": conversation}
+    })))
+}
+
+#[utoipa::path(
+    tag = "conversations",
+    params(
+        ("id" = Uuid, Path, description = "Conversation id"),
+    ),
+    responses(
+        (status = 200, description = "Conversation deleted", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 404, description = "Conversation not found", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[delete("/conversations/{id}")]
+async fn delete_conversation_handler(
+    path: web::Path<Uuid>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let result = sqlx::query!(
+        "DELETE FROM conversations WHERE id = $1 AND user_id = $2",
+        path.into_inner(),
+        jwt.user.id
+    )
+    .execute(&data.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Conversation"));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success"
+    })))
+}
+
+#[utoipa::path(
+    tag = "conversations",
+    params(
+        ("id" = Uuid, Path, description = "Conversation id"),
+    ),
+    responses(
+        (status = 200, description = "`message`: the reply text; `data.message`: the stored `ConversationMessage`", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 404, description = "Conversation not found", body = ErrorEnvelope),
+        (status = 422, description = "Validation failed or content rejected by moderation", body = ErrorEnvelope),
+        (status = 429, description = "Usage quota exceeded", body = ErrorEnvelope),
+        (status = 503, description = "No upstream available", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[post("/conversations/{id}/messages")]
+async fn send_message_handler(
+    path: web::Path<Uuid>,
+    body: web::Json<ConversationMessageSchema>,
+    jwt: jwt_auth::JwtClaims,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    body.validate()?;
+
+    moderation::check(&data, jwt.user_id, ContentKind::ChatPrompt, &body.message).await?;
+
+    let conversation = find_conversation(&data.db, path.into_inner(), jwt.user_id)
+        .await?
+        .ok_or(ApiError::NotFound("Conversation"))?;
+
+    // A deactivated template falls back to the default one for its language.
+    let template = match conversation.template_id {
+        Some(template_id) => template::find(&data.db, template_id).await?,
+        None => None,
+    };
+    let prompt_template = match template {
+        Some(template) if template.active => Some(template),
+        Some(template) => template::find_default(&data.db, &template.language).await?,
+        None => template::find_default(&data.db, template::DEFAULT_LANGUAGE).await?,
+    };
+
+    let history = load_history(
+        &data.db,
+        conversation.id,
+        data.env.conversation_history_turns,
+    )
+    .await?;
+
+    let params = GenerationParams::resolve(
+        prompt_template.as_ref(),
+        body.max_new_tokens,
+        body.temperature,
+        data.env.default_max_new_tokens,
+    );
+    let prompt = template::render(prompt_template.as_ref(), &history, &body.message);
+
+    let completion = gateway::complete_chat(&data, jwt.user_id, &prompt, params).await?;
+
+    let template_id = prompt_template.map(|t| t.id);
+
+    let mut tx = data.db.begin().await?;
+
+    sqlx::query_as!(
+        ConversationMessage,
+        "INSERT INTO conversation_messages (conversation_id, role, content, template_id) VALUES ($1, 'user', $2, $3) RETURNING id, conversation_id, role, content, template_id, created_at",
+        conversation.id,
+        body.message,
+        template_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let assistant_message = sqlx::query_as!(
+        ConversationMessage,
+        "INSERT INTO conversation_messages (conversation_id, role, content, template_id) VALUES ($1, 'assistant', $2, $3) RETURNING id, conversation_id, role, content, template_id, created_at",
+        conversation.id,
+        completion.text,
+        template_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let title: String = body.message.trim().chars().take(TITLE_MAX_CHARS).collect();
+    sqlx::query!(
+        "UPDATE conversations SET updated_at = NOW(), title = CASE WHEN title = '' THEN $2 ELSE title END WHERE id = $1",
+        conversation.id,
+        title
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "message": assistant_message.content,
+        "data": {"message": assistant_message},
+        "usage": {
+            "chat_tokens": completion.chat_tokens,
+            "source": completion.source.as_str()
+        }
+    })))
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(create_conversation_handler)
+        .service(list_conversations_handler)
+        .service(get_conversation_handler)
+        .service(update_conversation_handler)
+        .service(delete_conversation_handler)
+        .service(send_message_handler);
+}
@@ -0,0 +1,150 @@
+use actix_web::{
+    cookie::{time::Duration, Cookie},
+    HttpRequest,
+};
+
+use crate::config::CookiePolicy;
+
+const ACCESS_TOKEN: &str = "access_token";
+const REFRESH_TOKEN: &str = "refresh_token";
+const LOGGED_IN: &str = "logged_in";
+const CSRF_TOKEN: &str = "csrf_token";
+
+/// Cookies the frontend reads; the tokens stay out of reach of scripts.
+const SCRIPT_READABLE: [&str; 2] = [LOGGED_IN, CSRF_TOKEN];
+
+impl CookiePolicy {
+    /// `__Host-` needs `Path=/` and no `Domain`; anything else can only get
+    /// `__Secure-`.
+    fn name(&self, base: &str, path: &str) -> String {
+        match (self.prefixes, self.domain.is_none() && path == "/") {
+            (false, _) => base.to_string(),
+            (true, true) => format!("__Host-{}", base),
+            (true, false) => format!("__Secure-{}", base),
+        }
+    }
+
+    fn build(&self, base: &str, path: &str, value: String, max_age: Duration) -> Cookie<'static> {
+        let mut cookie = Cookie::build(self.name(base, path), value)
+            .path(path.to_string())
+            .max_age(max_age)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .http_only(!SCRIPT_READABLE.contains(&base))
+            .finish();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+
+    pub fn access_token(&self, token: String, max_age_minutes: i64) -> Cookie<'static> {
+        self.build(ACCESS_TOKEN, "/", token, Duration::minutes(max_age_minutes))
+    }
+
+    pub fn refresh_token(&self, token: String, max_age_minutes: i64) -> Cookie<'static> {
+        self.build(
+            REFRESH_TOKEN,
+            &self.refresh_path,
+            token,
+            Duration::minutes(max_age_minutes),
+        )
+    }
+
+    pub fn logged_in(&self, max_age_minutes: i64) -> Cookie<'static> {
+        self.build(
+            LOGGED_IN,
+            "/",
+            "true".to_string(),
+            Duration::minutes(max_age_minutes),
+        )
+    }
+
+    /// Echoed in `X-CSRF-Token` by the frontend; see `csrf`.
+    pub fn csrf_token(&self, token: String, max_age_minutes: i64) -> Cookie<'static> {
+        self.build(CSRF_TOKEN, "/", token, Duration::minutes(max_age_minutes))
+    }
+
+    /// Expired copies of every session cookie. Attributes must match the
+    /// originals or browsers keep them.
+    pub fn cleared(&self) -> [Cookie<'static>; 4] {
+        let expired = Duration::seconds(-1);
+        [
+            self.build(ACCESS_TOKEN, "/", String::new(), expired),
+            self.build(REFRESH_TOKEN, &self.refresh_path, String::new(), expired),
+            self.build(LOGGED_IN, "/", String::new(), expired),
+            self.build(CSRF_TOKEN, "/", String::new(), expired),
+        ]
+    }
+
+    /// Whether the browser sent a cookie that authenticates the request.
+    pub fn has_session(&self, req: &HttpRequest) -> bool {
+        self.read_access_token(req).is_some() || self.read_refresh_token(req).is_some()
+    }
+
+    pub fn read_access_token(&self, req: &HttpRequest) -> Option<String> {
+        req.cookie(&self.name(ACCESS_TOKEN, "/"))
+            .map(|c| c.value().to_string())
+    }
+
+    pub fn read_csrf_token(&self, req: &HttpRequest) -> Option<String> {
+        req.cookie(&self.name(CSRF_TOKEN, "/"))
+            .map(|c| c.value().to_string())
+    }
+
+    /// Only present on requests to `refresh_path`.
+    pub fn read_refresh_token(&self, req: &HttpRequest) -> Option<String> {
+        req.cookie(&self.name(REFRESH_TOKEN, &self.refresh_path))
+            .map(|c| c.value().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::cookie::SameSite;
+
+    use super::*;
+
+    fn policy() -> CookiePolicy {
+        CookiePolicy {
+            secure: true,
+            same_site: SameSite::Strict,
+            domain: None,
+            prefixes: true,
+            refresh_path: "/api/auth/refresh".to_string(),
+        }
+    }
+
+    #[test]
+    fn applies_policy_attributes() {
+        let access = policy().access_token("token".to_string(), 15);
+        assert_eq!(access.name(), "__Host-access_token");
+        assert_eq!(access.path(), Some("/"));
+        assert_eq!(access.secure(), Some(true));
+        assert_eq!(access.same_site(), Some(SameSite::Strict));
+        assert_eq!(access.http_only(), Some(true));
+
+        let refresh = policy().refresh_token("token".to_string(), 60);
+        assert_eq!(refresh.name(), "__Secure-refresh_token");
+        assert_eq!(refresh.path(), Some("/api/auth/refresh"));
+
+        let logged_in = policy().logged_in(15);
+        assert_eq!(logged_in.http_only(), Some(false));
+    }
+
+    #[test]
+    fn domain_rules_out_host_prefix() {
+        let policy = CookiePolicy {
+            domain: Some("tatarby.ru".to_string()),
+            ..policy()
+        };
+        let access = policy.access_token("token".to_string(), 15);
+        assert_eq!(access.name(), "__Secure-access_token");
+        assert_eq!(access.domain(), Some("tatarby.ru"));
+
+        for cookie in policy.cleared() {
+            assert_eq!(cookie.domain(), Some("tatarby.ru"));
+            assert!(cookie.max_age().unwrap().is_negative());
+        }
+    }
+}
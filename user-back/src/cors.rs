@@ -0,0 +1,139 @@
+use actix_cors::Cors;
+use actix_web::http::header::HeaderValue;
+
+use crate::config::{CorsPolicy, CorsScope};
+
+/// Whether `origin` is listed exactly or matches a `scheme://*.domain`
+/// pattern. The wildcard stands for one or more whole labels, never for a
+/// path or port.
+fn origin_allowed(origins: &[String], origin: &str) -> bool {
+    origins.iter().any(|allowed| match allowed.split_once('*') {
+        None => allowed == origin,
+        Some((prefix, suffix)) => origin
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(suffix))
+            .is_some_and(|label| {
+                !label.is_empty()
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+            }),
+    })
+}
+
+fn build(policy: &CorsPolicy, scope: &CorsScope) -> Cors {
+    let origins = policy.origins.clone();
+    Cors::default()
+        .allowed_origin_fn(move |origin: &HeaderValue, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| origin_allowed(&origins, origin))
+        })
+        .allowed_methods(scope.methods.iter().map(String::as_str))
+        .allowed_headers(scope.headers.iter().map(String::as_str))
+        .expose_headers(policy.expose_headers.iter().map(String::as_str))
+        .max_age(policy.max_age_secs)
+}
+
+/// For the `/api` scope, whose routes authenticate with cookies.
+pub fn api(policy: &CorsPolicy) -> Cors {
+    build(policy, &policy.api).supports_credentials()
+}
+
+/// For probes, docs and metrics.
+pub fn root(policy: &CorsPolicy) -> Cors {
+    build(policy, &policy.root)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        web, App, HttpResponse,
+    };
+
+    use super::*;
+
+    fn policy() -> CorsPolicy {
+        CorsPolicy {
+            origins: vec!["https://*.staging.tatarby.ru".to_string()],
+            api: CorsScope {
+                methods: vec!["GET".to_string(), "PATCH".to_string()],
+                headers: vec!["content-type".to_string()],
+            },
+            root: CorsScope {
+                methods: vec!["GET".to_string()],
+                headers: Vec::new(),
+            },
+            expose_headers: vec!["x-request-id".to_string()],
+            max_age_secs: 600,
+        }
+    }
+
+    #[actix_web::test]
+    async fn answers_preflight_per_scope() {
+        let policy = policy();
+        let app = init_service(
+            App::new()
+                .service(
+                    web::scope("/api")
+                        .route("/thing", web::patch().to(HttpResponse::Ok))
+                        .wrap(api(&policy)),
+                )
+                .service(
+                    web::scope("")
+                        .route("/livez", web::get().to(HttpResponse::Ok))
+                        .wrap(root(&policy)),
+                ),
+        )
+        .await;
+        let preflight = |uri: &'static str| {
+            TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri(uri)
+                .insert_header(("Origin", "https://pr-7.staging.tatarby.ru"))
+                .insert_header(("Access-Control-Request-Method", "PATCH"))
+                .to_request()
+        };
+
+        let response = call_service(&app, preflight("/api/thing")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers.get("access-control-allow-origin").unwrap(),
+            "https://pr-7.staging.tatarby.ru"
+        );
+        assert_eq!(
+            headers.get("access-control-allow-credentials").unwrap(),
+            "true"
+        );
+        assert_eq!(headers.get("access-control-max-age").unwrap(), "600");
+
+        let response = call_service(&app, preflight("/livez")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn matches_exact_origins_and_subdomain_patterns() {
+        let origins = [
+            "http://localhost:3000".to_string(),
+            "https://*.staging.tatarby.ru".to_string(),
+        ];
+        assert!(origin_allowed(&origins, "http://localhost:3000"));
+        assert!(origin_allowed(&origins, "https://pr-12.staging.tatarby.ru"));
+        assert!(origin_allowed(&origins, "https://a.b.staging.tatarby.ru"));
+
+        assert!(!origin_allowed(&origins, "http://localhost:3001"));
+        assert!(!origin_allowed(&origins, "https://staging.tatarby.ru"));
+        assert!(!origin_allowed(&origins, "http://pr-12.staging.tatarby.ru"));
+        assert!(!origin_allowed(
+            &origins,
+            "https://evil.com/.staging.tatarby.ru"
+        ));
+        assert!(!origin_allowed(
+            &origins,
+            "https://evil.com:443.staging.tatarby.ru"
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use std::future::Future;
+
+use actix_web::{
+    body::EitherBody,
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    http::{header, Method},
+    web, Error, HttpRequest, HttpResponse,
+};
+use base64::Engine;
+use rand::RngCore;
+
+use crate::{config::CookiePolicy, error::ApiError, AppState};
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// A fresh random token for the `csrf_token` cookie.
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Compares in time that depends only on the length.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// Double-submit check: a request that relies on session cookies and may
+/// change state must echo the `csrf_token` cookie in `X-CSRF-Token`. Other
+/// origins can make the browser send the cookie but cannot read it.
+///
+/// Requests with `Authorization: Bearer` are exempt, since no browser adds
+/// that header on its own, and so are requests without session cookies.
+fn verify(req: &HttpRequest, policy: &CookiePolicy) -> Result<(), ApiError> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.starts_with("Bearer "));
+    if bearer || !policy.has_session(req) {
+        return Ok(());
+    }
+
+    let header = req
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    match policy.read_csrf_token(req) {
+        Some(cookie) if !cookie.is_empty() && tokens_match(&cookie, header) => Ok(()),
+        _ => Err(ApiError::CsrfFailed),
+    }
+}
+
+/// Passes the request on when it satisfies `verify`; otherwise returns the
+/// `csrf_failed` response to send instead.
+pub fn check(req: ServiceRequest) -> Result<ServiceRequest, ServiceResponse> {
+    let verdict = match req.app_data::<web::Data<AppState>>() {
+        Some(data) => verify(req.request(), &data.env.cookie_policy),
+        None => Ok(()),
+    };
+    match verdict {
+        Ok(()) => Ok(req),
+        Err(e) => Err(req.error_response(e)),
+    }
+}
+
+/// Awaits the app for requests that passed `check`.
+pub async fn protect<B, F>(
+    outcome: Result<F, ServiceResponse>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error>
+where
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    match outcome {
+        Ok(response) => Ok(response.await?.map_into_left_body()),
+        Err(rejected) => Ok(rejected.map_into_right_body()),
+    }
+}
+
+#[utoipa::path(
+    tag = "auth",
+    responses(
+        (status = 200, description = "`csrf_token` to send back in the `X-CSRF-Token` header; also set as a cookie", body = Object),
+    )
+)]
+#[get("/auth/csrf")]
+async fn csrf_token_handler(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let policy = &data.env.cookie_policy;
+    // Reusing the current token keeps other open tabs working.
+    let token = policy
+        .read_csrf_token(&req)
+        .filter(|token| !token.is_empty())
+        .unwrap_or_else(new_token);
+
+    HttpResponse::Ok()
+        .cookie(policy.csrf_token(token.clone(), data.env.refresh_token_max_age))
+        .json(serde_json::json!({
+            "status": "success",
+            "csrf_token": token
+        }))
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(csrf_token_handler);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{cookie::Cookie, cookie::SameSite, test::TestRequest};
+
+    use super::*;
+
+    fn policy() -> CookiePolicy {
+        CookiePolicy {
+            secure: true,
+            same_site: SameSite::Lax,
+            domain: None,
+            prefixes: false,
+            refresh_path: "/api/auth/refresh".to_string(),
+        }
+    }
+
+    #[test]
+    fn requires_matching_header_for_cookie_sessions() {
+        let session = Cookie::new("access_token", "jwt");
+        let csrf = Cookie::new("csrf_token", "abc");
+
+        let req = TestRequest::post()
+            .cookie(session.clone())
+            .to_http_request();
+        assert!(verify(&req, &policy()).is_err());
+
+        let req = TestRequest::post()
+            .cookie(session.clone())
+            .cookie(csrf.clone())
+            .insert_header((CSRF_HEADER, "abd"))
+            .to_http_request();
+        assert!(verify(&req, &policy()).is_err());
+
+        let req = TestRequest::post()
+            .cookie(session.clone())
+            .cookie(csrf)
+            .insert_header((CSRF_HEADER, "abc"))
+            .to_http_request();
+        assert!(verify(&req, &policy()).is_ok());
+
+        let req = TestRequest::get().cookie(session).to_http_request();
+        assert!(verify(&req, &policy()).is_ok());
+    }
+
+    #[test]
+    fn exempts_bearer_and_anonymous_requests() {
+        let req = TestRequest::post()
+            .cookie(Cookie::new("access_token", "jwt"))
+            .insert_header((header::AUTHORIZATION, "Bearer jwt"))
+            .to_http_request();
+        assert!(verify(&req, &policy()).is_ok());
+
+        let req = TestRequest::post().to_http_request();
+        assert!(verify(&req, &policy()).is_ok());
+    }
+}
@@ -0,0 +1,242 @@
+use actix_web::{
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    http::StatusCode,
+    HttpRequest, HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use validator::ValidationErrors;
+
+use crate::{
+    i18n::{self, Locale},
+    model::UsageKind,
+    quota::QuotaError,
+    session_store::StoreError,
+    upstream::UpstreamError,
+    validation,
+};
+
+/// Every error a handler or extractor can return. Each variant maps to one
+/// HTTP status and one stable `code` that clients can match on.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    InvalidCredentials,
+    Unauthorized(String),
+    InvalidToken(String),
+    InvalidProof(String),
+    Forbidden(String),
+    AdminRequired,
+    CsrfFailed,
+    NotFound(&'static str),
+    Conflict(String),
+    Validation(ValidationErrors),
+    ContentRejected,
+    QuotaExceeded(UsageKind),
+    UpstreamUnavailable,
+    Upstream(String),
+    Database(sqlx::Error),
+    Store(StoreError),
+    Token(jsonwebtoken::errors::Error),
+    Internal(String),
+}
+
+/// The body of every error response.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ErrorEnvelope {
+    /// `fail` for client errors, `error` for server errors.
+    pub status: &'static str,
+    pub code: &'static str,
+    pub message: String,
+    /// Per-field messages for `validation_failed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::InvalidToken(_) => "invalid_token",
+            ApiError::InvalidProof(_) => "invalid_dpop_proof",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::AdminRequired => "admin_required",
+            ApiError::CsrfFailed => "csrf_failed",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::ContentRejected => "content_rejected",
+            ApiError::QuotaExceeded(_) => "quota_exceeded",
+            ApiError::UpstreamUnavailable => "upstream_unavailable",
+            ApiError::Upstream(_) => "upstream_error",
+            ApiError::Database(_) => "database_error",
+            ApiError::Store(_) => "cache_error",
+            ApiError::Token(_) => "token_error",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The message sent to the client. Server errors get a generic message so
+    /// driver and key details stay in the logs.
+    fn public_message(&self, locale: Locale) -> String {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::InvalidToken(message)
+            | ApiError::InvalidProof(message)
+            | ApiError::Forbidden(message)
+            | ApiError::Conflict(message)
+            | ApiError::Upstream(message) => i18n::translate(locale, self.code(), message).into(),
+            ApiError::NotFound(what) => i18n::text(locale, "not_found")
+                .replace("{what}", &i18n::translate(locale, "noun", what)),
+            ApiError::QuotaExceeded(kind) => i18n::text(locale, "quota_exceeded")
+                .replace("{kind}", &i18n::translate(locale, "kind", kind.as_str())),
+            ApiError::Database(_)
+            | ApiError::Store(_)
+            | ApiError::Token(_)
+            | ApiError::Internal(_) => i18n::text(locale, "internal_error").to_string(),
+            _ => i18n::text(locale, self.code()).to_string(),
+        }
+    }
+
+    /// The error envelope with messages in `locale`.
+    pub fn render(&self, locale: Locale) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status).json(ErrorEnvelope {
+            status: if status.is_server_error() {
+                "error"
+            } else {
+                "fail"
+            },
+            code: self.code(),
+            message: self.public_message(locale),
+            errors: match self {
+                ApiError::Validation(errors) => Some(validation::field_messages(errors, locale)),
+                _ => None,
+            },
+        })
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::InvalidToken(message)
+            | ApiError::InvalidProof(message)
+            | ApiError::Forbidden(message)
+            | ApiError::Conflict(message)
+            | ApiError::Upstream(message)
+            | ApiError::Internal(message) => write!(f, "{}", message),
+            ApiError::InvalidCredentials => write!(f, "Invalid email or password"),
+            ApiError::AdminRequired => write!(f, "Access denied: admin privileges required"),
+            ApiError::CsrfFailed => write!(f, "Missing or invalid CSRF token"),
+            ApiError::NotFound(what) => write!(f, "{} not found", what),
+            ApiError::Validation(_) => write!(f, "Request validation failed"),
+            ApiError::ContentRejected => write!(f, "Content was rejected by moderation"),
+            ApiError::QuotaExceeded(kind) => {
+                write!(f, "Usage quota exceeded for {}", kind.as_str())
+            }
+            ApiError::UpstreamUnavailable => write!(f, "no upstream available"),
+            ApiError::Database(e) => write!(f, "database error: {}", e),
+            ApiError::Store(e) => write!(f, "session store error: {}", e),
+            ApiError::Token(e) => write!(f, "token error: {}", e),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidCredentials
+            | ApiError::Unauthorized(_)
+            | ApiError::InvalidToken(_)
+            | ApiError::InvalidProof(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) | ApiError::AdminRequired | ApiError::CsrfFailed => {
+                StatusCode::FORBIDDEN
+            }
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Validation(_) | ApiError::ContentRejected => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::UpstreamUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Database(_)
+            | ApiError::Store(_)
+            | ApiError::Token(_)
+            | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code().is_server_error() {
+            tracing::error!(code = self.code(), error = %self, "ошибка");
+        }
+
+        self.render(Locale::En)
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(e: ValidationErrors) -> Self {
+        ApiError::Validation(e)
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        ApiError::Store(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for ApiError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        ApiError::Token(e)
+    }
+}
+
+impl From<QuotaError> for ApiError {
+    fn from(e: QuotaError) -> Self {
+        match e {
+            QuotaError::Exceeded(kind) => ApiError::QuotaExceeded(kind),
+            QuotaError::Store(e) => ApiError::Store(e),
+            QuotaError::Database(e) => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<UpstreamError> for ApiError {
+    fn from(e: UpstreamError) -> Self {
+        match e {
+            UpstreamError::Unavailable => ApiError::UpstreamUnavailable,
+            e => ApiError::Upstream(e.to_string()),
+        }
+    }
+}
+
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(err.to_string()).into()
+}
+
+pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(err.to_string()).into()
+}
+
+pub fn path_error_handler(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(err.to_string()).into()
+}
+
+/// Fallback for unknown routes so they answer with the same envelope.
+pub async fn not_found_handler() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NotFound("Route"))
+}
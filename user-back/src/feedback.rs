@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{ApiError, ErrorEnvelope},
+    jwt_auth,
+    model::{FeedbackExportQuery, FeedbackSchema, MessageFeedback, PromptTemplate},
+    template, AppState,
+};
+
+use actix_web::{delete, get, http::header, put, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use serde::Serialize;
+use uuid::Uuid;
+use validator::Validate;
+
+/// A rated assistant reply together with the user prompt that preceded it.
+struct RatedPair {
+    prompt: Option<String>,
+    response: String,
+    template_id: Option<Uuid>,
+    rating: String,
+    correction: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+/// One line of the export, in the instruction/input/output layout used by
+/// common instruction-tuning scripts.
+#[derive(Debug, Serialize)]
+struct InstructionRecord<'a> {
+    instruction: &'a str,
+    input: &'a str,
+    output: &'a str,
+    system: Option<String>,
+    language: Option<&'a str>,
+    template: Option<&'a str>,
+    rating: &'a str,
+    /// The model's reply when `output` is a user correction.
+    original_output: Option<&'a str>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    tag = "feedback",
+    params(
+        ("id" = Uuid, Path, description = "Conversation id"),
+        ("message_id" = Uuid, Path, description = "Assistant message id"),
+    ),
+    responses(
+        (status = 200, description = "`data.feedback`: the stored `MessageFeedback`", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 404, description = "Assistant message not found", body = ErrorEnvelope),
+        (status = 422, description = "Request validation failed", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[put("/conversations/{id}/messages/{message_id}/feedback")]
+async fn put_feedback_handler(
+    path: web::Path<(Uuid, Uuid)>,
+    body: web::Json<FeedbackSchema>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    body.validate()?;
+
+    let (conversation_id, message_id) = path.into_inner();
+
+    let message_exists = sqlx::query_scalar!(
+        "SELECT EXISTS(
+            SELECT 1 FROM conversation_messages m
+            JOIN conversations c ON c.id = m.conversation_id
+            WHERE m.id = $1 AND m.conversation_id = $2 AND c.user_id = $3 AND m.role = 'assistant'
+        )",
+        message_id,
+        conversation_id,
+        jwt.user.id
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    if message_exists != Some(true) {
+        return Err(ApiError::NotFound("Assistant message"));
+    }
+
+    let correction = body
+        .correction
+        .as_deref()
+        .map(str::trim)
+        .filter(|correction| !correction.is_empty());
+
+    let feedback = sqlx::query_as!(
+        MessageFeedback,
+        "INSERT INTO message_feedback (message_id, user_id, rating, correction, consent)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (message_id) DO UPDATE SET
+            rating = EXCLUDED.rating,
+            correction = EXCLUDED.correction,
+            consent = EXCLUDED.consent,
+            updated_at = NOW()
+         RETURNING id, message_id, rating, correction, consent, created_at, updated_at",
+        message_id,
+        jwt.user.id,
+        body.rating.as_str(),
+        correction,
+        body.consent.unwrap_or(false)
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"feedback": feedback}
+    })))
+}
+
+#[utoipa::path(
+    tag = "feedback",
+    params(
+        ("id" = Uuid, Path, description = "Conversation id"),
+        ("message_id" = Uuid, Path, description = "Assistant message id"),
+    ),
+    responses(
+        (status = 200, description = "Feedback deleted", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 404, description = "Feedback not found", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[delete("/conversations/{id}/messages/{message_id}/feedback")]
+async fn delete_feedback_handler(
+    path: web::Path<(Uuid, Uuid)>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let (conversation_id, message_id) = path.into_inner();
+
+    let result = sqlx::query!(
+        "DELETE FROM message_feedback f
+         USING conversation_messages m
+         WHERE f.message_id = m.id AND f.message_id = $1 AND m.conversation_id = $2 AND f.user_id = $3",
+        message_id,
+        conversation_id,
+        jwt.user.id
+    )
+    .execute(&data.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Feedback"));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success"
+    })))
+}
+
+/// Exports consented ratings as JSONL. Only feedback the user explicitly
+/// agreed to share is ever included.
+#[utoipa::path(
+    tag = "feedback",
+    params(
+        FeedbackExportQuery,
+    ),
+    responses(
+        (status = 200, description = "One prompt/response pair per line for users who consented", content_type = "application/x-ndjson", body = String),
+        (status = 401, description = "Missing, invalid or expired access token, or a missing or invalid `DPoP` proof", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[get("/admins/feedback/export")]
+async fn export_feedback_handler(
+    req: HttpRequest,
+    query: web::Query<FeedbackExportQuery>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+    jwt.require_proof(&req, &data).await?;
+
+    let templates: HashMap<Uuid, PromptTemplate> = sqlx::query_as!(
+        PromptTemplate,
+        "SELECT id, name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, active, created_at, updated_at FROM prompt_templates"
+    )
+    .fetch_all(&data.db)
+    .await?
+    .into_iter()
+    .map(|t| (t.id, t))
+    .collect();
+
+    // Rows are sent as they arrive, so the export never sits in memory. The
+    // channel is bounded: a slow client holds the query back, and one that
+    // goes away ends it.
+    let (mut lines, body) = mpsc::channel::<Result<web::Bytes, actix_web::Error>>(64);
+    let db = data.db.clone();
+    let query = query.into_inner();
+    actix_web::rt::spawn(async move {
+        let mut pairs = sqlx::query_as!(
+            RatedPair,
+            r#"SELECT
+            (SELECT u.content FROM conversation_messages u
+             WHERE u.conversation_id = m.conversation_id AND u.role = 'user' AND u.created_at < m.created_at
+             ORDER BY u.created_at DESC LIMIT 1) AS prompt,
+            m.content AS response,
+            m.template_id,
+            f.rating,
+            f.correction,
+            f.created_at
+         FROM message_feedback f
+         JOIN conversation_messages m ON m.id = f.message_id
+         WHERE f.consent
+           AND ($1::timestamptz IS NULL OR f.created_at >= $1)
+           AND ($2::timestamptz IS NULL OR f.created_at < $2)
+           AND ($3::varchar IS NULL OR f.rating = $3)
+           AND ($4::uuid IS NULL OR m.template_id = $4)
+         ORDER BY f.created_at"#,
+            query.from,
+            query.to,
+            query.rating.map(|rating| rating.as_str()),
+            query.template_id
+        )
+        .fetch(&db);
+
+        while let Some(pair) = pairs.next().await {
+            let line = match pair {
+                Ok(pair) => match instruction_line(&pair, &templates) {
+                    Ok(Some(line)) => Ok(web::Bytes::from(line)),
+                    Ok(None) => continue,
+                    Err(e) => Err(ApiError::Internal(e.to_string())),
+                },
+                Err(e) => Err(ApiError::from(e)),
+            };
+            // Headers are already out, so an error can only cut the body
+            // short; the client sees the connection end mid-stream.
+            let failed = line.is_err();
+            if let Err(e) = &line {
+                tracing::error!(error = %e, "ошибка выгрузки отзывов");
+            }
+            if lines.send(line.map_err(Into::into)).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"feedback.jsonl\"",
+        ))
+        .streaming(body))
+}
+
+/// The export line for `pair`, or `None` when its prompt is gone.
+fn instruction_line(
+    pair: &RatedPair,
+    templates: &HashMap<Uuid, PromptTemplate>,
+) -> Result<Option<String>, serde_json::Error> {
+    let prompt = match &pair.prompt {
+        Some(prompt) => prompt,
+        None => return Ok(None),
+    };
+    let prompt_template = pair.template_id.and_then(|id| templates.get(&id));
+
+    let record = InstructionRecord {
+        instruction: prompt,
+        input: "",
+        output: pair.correction.as_deref().unwrap_or(&pair.response),
+        system: prompt_template.map(template::system_prompt),
+        language: prompt_template.map(|t| t.language.as_str()),
+        template: prompt_template.map(|t| t.name.as_str()),
+        rating: &pair.rating,
+        original_output: pair.correction.as_ref().map(|_| pair.response.as_str()),
+        created_at: pair.created_at,
+    };
+
+    let mut line = serde_json::to_string(&record)?;
+    line.push('\n');
+    Ok(Some(line))
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(put_feedback_handler)
+        .service(delete_feedback_handler)
+        .service(export_feedback_handler);
+}
@@ -0,0 +1,293 @@
+use crate::{
+    error::{ApiError, ErrorEnvelope},
+    jwt_auth,
+    model::{ChatRequestSchema, ImageRequestSchema, UsageKind},
+    moderation::{self, ContentKind},
+    quota::{self, QuotaSource},
+    template::{self, GenerationParams},
+    upstream::{UpstreamError, UpstreamPool},
+    AppState,
+};
+
+use actix_web::{post, web, HttpResponse};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Serialize)]
+struct LlmMessage<'a> {
+    content: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct LlmChatRequest<'a> {
+    messages: Vec<LlmMessage<'a>>,
+    /// The prompt is already rendered from a template and must be passed to
+    /// the model as is.
+    raw_prompt: bool,
+    max_new_tokens: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LlmChatResponse {
+    response: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DiffusionRequest<'a> {
+    prompt: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    negative_prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_inference_steps: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    guidance_scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    return_base64: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiffusionResponse {
+    image: Option<String>,
+    success: bool,
+    message: String,
+}
+
+/// Posts `payload` to `path` on one of the pool's upstreams and decodes the
+/// JSON reply.
+async fn forward<T, R>(
+    pool: &UpstreamPool,
+    client: &reqwest::Client,
+    path: &str,
+    payload: &T,
+) -> Result<R, UpstreamError>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    let response = pool
+        .send(client, |client, url| {
+            client.post(format!("{}{}", url, path)).json(payload)
+        })
+        .await?;
+
+    response
+        .error_for_status()
+        .map_err(UpstreamError::Request)?
+        .json::<R>()
+        .await
+        .map_err(UpstreamError::Request)
+}
+
+/// Result of a chat completion that has been charged to the user's quota.
+pub struct Completion {
+    pub text: String,
+    pub chat_tokens: i64,
+    pub source: QuotaSource,
+}
+
+/// Reserves quota for `prompt`, sends it to the LLM pool and settles the
+/// reservation.
+pub async fn complete_chat(
+    data: &AppState,
+    user_id: Uuid,
+    prompt: &str,
+    params: GenerationParams,
+) -> Result<Completion, ApiError> {
+    let (plan, _) = quota::load_user_plan(&data.db, user_id, &data.env.default_plan).await?;
+
+    let store = data.session_store.as_ref();
+
+    let prompt_tokens = quota::estimate_tokens(prompt);
+
+    let reservation = quota::reserve(
+        store,
+        &data.db,
+        &plan,
+        user_id,
+        UsageKind::ChatTokens,
+        prompt_tokens + params.max_new_tokens,
+    )
+    .await?;
+
+    let request = LlmChatRequest {
+        messages: vec![LlmMessage { content: prompt }],
+        raw_prompt: true,
+        max_new_tokens: params.max_new_tokens,
+        temperature: params.temperature,
+    };
+
+    let reply =
+        match forward::<_, LlmChatResponse>(&data.llm_pool, &data.http_client, "/chat", &request)
+            .await
+        {
+            Ok(reply) => reply,
+            Err(e) => {
+                if let Err(release_err) = quota::release(store, &data.db, &reservation).await {
+                    tracing::error!(error = ?release_err, "не удалось вернуть квоту");
+                }
+                return Err(e.into());
+            }
+        };
+
+    let chat_tokens = prompt_tokens + quota::estimate_tokens(&reply.response);
+    if let Err(e) = quota::commit(store, &data.db, &reservation, chat_tokens).await {
+        tracing::error!(error = ?e, "не удалось записать использование");
+    }
+
+    moderation::check(data, user_id, ContentKind::ChatOutput, &reply.response).await?;
+
+    Ok(Completion {
+        text: reply.response,
+        chat_tokens,
+        source: reservation.source,
+    })
+}
+
+#[utoipa::path(
+    tag = "gateway",
+    responses(
+        (status = 200, description = "`message`: the completion text", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 422, description = "Validation failed or content rejected by moderation", body = ErrorEnvelope),
+        (status = 429, description = "Usage quota exceeded", body = ErrorEnvelope),
+        (status = 503, description = "No upstream available", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[post("/chat")]
+async fn chat_handler(
+    body: web::Json<ChatRequestSchema>,
+    jwt: jwt_auth::JwtClaims,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    body.validate()?;
+
+    moderation::check(&data, jwt.user_id, ContentKind::ChatPrompt, &body.message).await?;
+
+    let prompt_template = match body.template_id {
+        Some(template_id) => Some(
+            template::find_active(&data.db, template_id)
+                .await?
+                .ok_or_else(|| ApiError::BadRequest("Unknown template".to_string()))?,
+        ),
+        None => {
+            template::find_default(
+                &data.db,
+                body.language
+                    .as_deref()
+                    .unwrap_or(template::DEFAULT_LANGUAGE),
+            )
+            .await?
+        }
+    };
+
+    let params = GenerationParams::resolve(
+        prompt_template.as_ref(),
+        body.max_new_tokens,
+        body.temperature,
+        data.env.default_max_new_tokens,
+    );
+    let prompt = template::render(prompt_template.as_ref(), &[], &body.message);
+
+    let completion = complete_chat(&data, jwt.user_id, &prompt, params).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "message": completion.text,
+        "template_id": prompt_template.map(|t| t.id),
+        "usage": {
+            "chat_tokens": completion.chat_tokens,
+            "source": completion.source.as_str()
+        }
+    })))
+}
+
+#[utoipa::path(
+    tag = "gateway",
+    responses(
+        (status = 200, description = "Generated image as returned by the diffusion service", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 422, description = "Validation failed or content rejected by moderation", body = ErrorEnvelope),
+        (status = 429, description = "Usage quota exceeded", body = ErrorEnvelope),
+        (status = 503, description = "No upstream available", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[post("/images/generate")]
+async fn generate_image_handler(
+    body: web::Json<ImageRequestSchema>,
+    jwt: jwt_auth::JwtClaims,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    body.validate()?;
+
+    moderation::check(&data, jwt.user_id, ContentKind::ImagePrompt, &body.prompt).await?;
+
+    let (plan, _) = quota::load_user_plan(&data.db, jwt.user_id, &data.env.default_plan).await?;
+
+    let store = data.session_store.as_ref();
+
+    let reservation =
+        quota::reserve(store, &data.db, &plan, jwt.user_id, UsageKind::Images, 1).await?;
+
+    let request = DiffusionRequest {
+        prompt: &body.prompt,
+        negative_prompt: body.negative_prompt.as_deref(),
+        num_inference_steps: body.num_inference_steps,
+        guidance_scale: body.guidance_scale,
+        width: body.width,
+        height: body.height,
+        seed: body.seed,
+        return_base64: true,
+    };
+
+    let generated = forward::<_, DiffusionResponse>(
+        &data.diffusion_pool,
+        &data.http_client,
+        "/generate",
+        &request,
+    )
+    .await;
+
+    let image = match generated {
+        Ok(DiffusionResponse {
+            image: Some(image),
+            success: true,
+            ..
+        }) => image,
+        failed => {
+            if let Err(release_err) = quota::release(store, &data.db, &reservation).await {
+                tracing::error!(error = ?release_err, "не удалось вернуть квоту");
+            }
+            return Err(match failed {
+                Ok(DiffusionResponse { message, .. }) => ApiError::Upstream(message),
+                Err(e) => e.into(),
+            });
+        }
+    };
+
+    if let Err(e) = quota::commit(store, &data.db, &reservation, 1).await {
+        tracing::error!(error = ?e, "не удалось записать использование");
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "imageUrl": format!("data:image/png;base64,{}", image),
+        "usage": {
+            "images": 1,
+            "source": reservation.source.as_str()
+        }
+    })))
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(chat_handler).service(generate_image_handler);
+}
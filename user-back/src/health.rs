@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use sysinfo::System;
+use utoipa::ToSchema;
+
+use crate::{session_store::SessionStore, AppState};
+
+/// Memory and CPU figures as of the last background refresh.
+#[derive(Debug, Clone, Copy, Default, Serialize, ToSchema)]
+pub struct SystemSnapshot {
+    pub memory_used_mb: u64,
+    pub memory_total_mb: u64,
+    pub cpu_usage_percent: f64,
+    pub refreshed_at: Option<DateTime<Utc>>,
+}
+
+/// Holds the latest `SystemSnapshot` so probes never scan the system
+/// themselves.
+#[derive(Debug, Default)]
+pub struct SystemStats {
+    snapshot: RwLock<SystemSnapshot>,
+}
+
+impl SystemStats {
+    pub fn snapshot(&self) -> SystemSnapshot {
+        *self.snapshot.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub async fn run_system_refresh(stats: Arc<SystemStats>, interval_secs: u64) {
+    let mut system = System::new();
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        system.refresh_memory();
+        system.refresh_cpu_usage();
+
+        *stats.snapshot.write().unwrap_or_else(|e| e.into_inner()) = SystemSnapshot {
+            memory_used_mb: system.used_memory() / 1024 / 1024,
+            memory_total_mb: system.total_memory() / 1024 / 1024,
+            cpu_usage_percent: system.global_cpu_usage() as f64,
+            refreshed_at: Some(Utc::now()),
+        };
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DependencyCheck {
+    pub healthy: bool,
+    /// Whether a failure of this dependency makes the service not ready.
+    pub required: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DependencyCheck {
+    /// The probes are unauthenticated, so the details of a failure only go
+    /// to the log.
+    fn from_result(name: &str, started: Instant, result: Result<(), String>) -> Self {
+        if let Err(e) = &result {
+            tracing::warn!(check = name, error = %e, "проверка готовности не прошла");
+        }
+        DependencyCheck {
+            healthy: result.is_ok(),
+            required: true,
+            latency_ms: started.elapsed().as_millis() as u64,
+            error: result.err().map(|_| "unavailable".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: BTreeMap<&'static str, DependencyCheck>,
+}
+
+async fn with_timeout<F, E>(timeout: Duration, check: F) -> Result<(), String>
+where
+    F: std::future::Future<Output = Result<(), E>>,
+    E: std::fmt::Display,
+{
+    match actix_web::rt::time::timeout(timeout, check).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("timed out after {} ms", timeout.as_millis())),
+    }
+}
+
+async fn check_postgres(db: &Pool<Postgres>, timeout: Duration) -> DependencyCheck {
+    let started = Instant::now();
+    let result = with_timeout(timeout, async {
+        sqlx::query("SELECT 1").execute(db).await.map(|_| ())
+    })
+    .await;
+    DependencyCheck::from_result("postgres", started, result)
+}
+
+async fn check_session_store(store: &dyn SessionStore, timeout: Duration) -> DependencyCheck {
+    let started = Instant::now();
+    let result = with_timeout(timeout, store.ping()).await;
+    DependencyCheck::from_result(store.name(), started, result)
+}
+
+/// Upstream pools are probed in the background, so this only reads their
+/// last known state.
+fn check_upstreams(data: &AppState) -> DependencyCheck {
+    let unavailable: Vec<&str> = [&data.llm_pool, &data.diffusion_pool]
+        .into_iter()
+        .filter(|pool| !pool.has_available())
+        .map(|pool| pool.name())
+        .collect();
+
+    DependencyCheck {
+        healthy: unavailable.is_empty(),
+        required: data.env.readiness_requires_upstreams,
+        latency_ms: 0,
+        error: (!unavailable.is_empty())
+            .then(|| format!("no available upstream in: {}", unavailable.join(", "))),
+    }
+}
+
+pub async fn readiness(data: &AppState) -> Readiness {
+    let timeout = Duration::from_millis(data.env.health_check_timeout_ms);
+    let (postgres, session_store) = futures::join!(
+        check_postgres(&data.db, timeout),
+        check_session_store(data.session_store.as_ref(), timeout)
+    );
+
+    let checks = BTreeMap::from([
+        ("postgres", postgres),
+        (data.session_store.name(), session_store),
+        ("upstreams", check_upstreams(data)),
+    ]);
+
+    Readiness {
+        ready: checks
+            .values()
+            .all(|check| check.healthy || !check.required),
+        checks,
+    }
+}
+
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "The process is running", body = Object)
+    )
+)]
+#[get("/livez")]
+async fn livez_handler() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({"status": "ok"}))
+}
+
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "All required dependencies are healthy", body = Readiness),
+        (status = 503, description = "A required dependency is failing", body = Readiness)
+    )
+)]
+#[get("/readyz")]
+async fn readyz_handler(data: web::Data<AppState>) -> HttpResponse {
+    let readiness = readiness(&data).await;
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+/// Probes live at the root, outside `/api`, so orchestrators can reach them
+/// without going through the API prefix.
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(livez_handler).service(readyz_handler);
+}
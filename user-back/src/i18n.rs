@@ -0,0 +1,564 @@
+use std::borrow::Cow;
+use std::future::Future;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::ServiceResponse,
+    http::header,
+    web, Error, HttpMessage, HttpRequest,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    Tt,
+    Ru,
+    En,
+}
+
+impl Locale {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::Tt => "tt",
+            Locale::Ru => "ru",
+            Locale::En => "en",
+        }
+    }
+
+    /// Parses a language tag such as `tt`, `ru-RU` or `en_US`; only the
+    /// primary subtag matters.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "tt" => Some(Locale::Tt),
+            "ru" => Some(Locale::Ru),
+            "en" => Some(Locale::En),
+            _ => None,
+        }
+    }
+
+    /// Picks the supported language with the highest `q` from an
+    /// `Accept-Language` header; ties go to the one listed first.
+    pub fn from_accept_language(value: &str) -> Option<Locale> {
+        let mut best: Option<(Locale, f32)> = None;
+
+        for entry in value.split(',') {
+            let mut parts = entry.split(';');
+            let locale = match parts.next().and_then(Locale::from_tag) {
+                Some(locale) => locale,
+                None => continue,
+            };
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                best = Some((locale, quality));
+            }
+        }
+
+        best.map(|(locale, _)| locale)
+    }
+
+    pub fn from_request(req: &HttpRequest) -> Option<Locale> {
+        req.headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Locale::from_accept_language)
+    }
+}
+
+struct Message {
+    key: &'static str,
+    en: &'static str,
+    tt: &'static str,
+    ru: &'static str,
+}
+
+impl Message {
+    fn get(&self, locale: Locale) -> &'static str {
+        match locale {
+            Locale::Tt => self.tt,
+            Locale::Ru => self.ru,
+            Locale::En => self.en,
+        }
+    }
+}
+
+/// Keys are error codes for the generic message of that code, and
+/// `code.detail` for the specific messages handlers return under it.
+/// `noun.*` and `kind.*` fill the placeholders of `not_found` and
+/// `quota_exceeded`; `validation.*` are the per-field messages.
+const CATALOG: &[Message] = &[
+    Message {
+        key: "bad_request",
+        en: "Invalid request",
+        tt: "Сорау дөрес түгел",
+        ru: "Некорректный запрос",
+    },
+    Message {
+        key: "bad_request.unknown_plan",
+        en: "Unknown plan",
+        tt: "Билгесез тариф",
+        ru: "Неизвестный тариф",
+    },
+    Message {
+        key: "bad_request.unknown_template",
+        en: "Unknown template",
+        tt: "Билгесез шаблон",
+        ru: "Неизвестный шаблон",
+    },
+    Message {
+        key: "bad_request.review_status",
+        en: "Review status must be confirmed or dismissed",
+        tt: "Тикшерү статусы confirmed яки dismissed булырга тиеш",
+        ru: "Статус проверки должен быть confirmed или dismissed",
+    },
+    Message {
+        key: "invalid_credentials",
+        en: "Invalid email or password",
+        tt: "Email яки серсүз дөрес түгел",
+        ru: "Неверный email или пароль",
+    },
+    Message {
+        key: "unauthorized",
+        en: "You are not logged in, please provide token",
+        tt: "Сез системага кермәгәнсез, токен җибәрегез",
+        ru: "Вы не вошли в систему, передайте токен",
+    },
+    Message {
+        key: "invalid_token",
+        en: "Token is invalid or session has expired",
+        tt: "Токен дөрес түгел яки сессия вакыты чыккан",
+        ru: "Токен недействителен или сессия истекла",
+    },
+    Message {
+        key: "invalid_token.user_id",
+        en: "Invalid user ID in token",
+        tt: "Токендагы кулланучы ID дөрес түгел",
+        ru: "Неверный ID пользователя в токене",
+    },
+    Message {
+        key: "invalid_token.user_gone",
+        en: "The user belonging to this token no longer exists",
+        tt: "Бу токенның кулланучысы инде юк",
+        ru: "Пользователь этого токена больше не существует",
+    },
+    Message {
+        key: "invalid_dpop_proof",
+        en: "DPoP proof is missing or invalid",
+        tt: "DPoP исбаты юк яки дөрес түгел",
+        ru: "DPoP-подтверждение отсутствует или недействительно",
+    },
+    Message {
+        key: "invalid_dpop_proof.other_request",
+        en: "DPoP proof was made for a different request",
+        tt: "DPoP исбаты башка сорау өчен ясалган",
+        ru: "DPoP-подтверждение создано для другого запроса",
+    },
+    Message {
+        key: "invalid_dpop_proof.expired",
+        en: "DPoP proof has expired",
+        tt: "DPoP исбатының вакыты чыккан",
+        ru: "Срок действия DPoP-подтверждения истёк",
+    },
+    Message {
+        key: "invalid_dpop_proof.replayed",
+        en: "DPoP proof has already been used",
+        tt: "DPoP исбаты инде кулланылган",
+        ru: "DPoP-подтверждение уже использовано",
+    },
+    Message {
+        key: "invalid_dpop_proof.key_mismatch",
+        en: "DPoP proof is not signed with the key the token is bound to",
+        tt: "DPoP исбаты токен бәйләнгән ачкыч белән имзаланмаган",
+        ru: "DPoP-подтверждение подписано не тем ключом, к которому привязан токен",
+    },
+    Message {
+        key: "invalid_dpop_proof.bound_token_required",
+        en: "This request requires an access token bound to a DPoP key",
+        tt: "Бу сорау өчен DPoP ачкычына бәйләнгән керү токены кирәк",
+        ru: "Для этого запроса нужен токен доступа, привязанный к DPoP-ключу",
+    },
+    Message {
+        key: "forbidden",
+        en: "Access denied",
+        tt: "Керү тыела",
+        ru: "Доступ запрещён",
+    },
+    Message {
+        key: "forbidden.refresh_failed",
+        en: "could not refresh access token",
+        tt: "Керү токенын яңартып булмады",
+        ru: "Не удалось обновить токен доступа",
+    },
+    Message {
+        key: "forbidden.user_gone",
+        en: "the user belonging to this token no longer exists",
+        tt: "Бу токенның кулланучысы инде юк",
+        ru: "Пользователь этого токена больше не существует",
+    },
+    Message {
+        key: "forbidden.session_expired",
+        en: "Token is invalid or session has expired",
+        tt: "Токен дөрес түгел яки сессия вакыты чыккан",
+        ru: "Токен недействителен или сессия истекла",
+    },
+    Message {
+        key: "admin_required",
+        en: "Access denied: admin privileges required",
+        tt: "Керү тыела: администратор хокуклары кирәк",
+        ru: "Доступ запрещён: нужны права администратора",
+    },
+    Message {
+        key: "csrf_failed",
+        en: "Missing or invalid CSRF token",
+        tt: "CSRF токены юк яки дөрес түгел",
+        ru: "CSRF-токен отсутствует или недействителен",
+    },
+    Message {
+        key: "not_found",
+        en: "{what} not found",
+        tt: "Табылмады: {what}",
+        ru: "Не найдено: {what}",
+    },
+    Message {
+        key: "conflict",
+        en: "Resource already exists",
+        tt: "Мондый ресурс инде бар",
+        ru: "Такой ресурс уже существует",
+    },
+    Message {
+        key: "conflict.email_taken",
+        en: "User with that email already exists",
+        tt: "Мондый email белән кулланучы инде бар",
+        ru: "Пользователь с таким email уже существует",
+    },
+    Message {
+        key: "conflict.template_name_taken",
+        en: "Template with that name already exists",
+        tt: "Мондый исемле шаблон инде бар",
+        ru: "Шаблон с таким именем уже существует",
+    },
+    Message {
+        key: "conflict.template_taken",
+        en: "Template name or default language is already taken",
+        tt: "Шаблон исеме яки тел өчен төп шаблон инде алынган",
+        ru: "Имя шаблона или шаблон по умолчанию для языка уже заняты",
+    },
+    Message {
+        key: "validation_failed",
+        en: "Request validation failed",
+        tt: "Сорау тикшерүне узмады",
+        ru: "Запрос не прошёл проверку",
+    },
+    Message {
+        key: "content_rejected",
+        en: "Content was rejected by moderation",
+        tt: "Эчтәлек модерация тарафыннан кире кагылды",
+        ru: "Содержимое отклонено модерацией",
+    },
+    Message {
+        key: "quota_exceeded",
+        en: "Usage quota exceeded for {kind}",
+        tt: "Куллану квотасы тулды: {kind}",
+        ru: "Превышена квота использования: {kind}",
+    },
+    Message {
+        key: "upstream_unavailable",
+        en: "no upstream available",
+        tt: "Генерация хезмәте хәзерге вакытта эшләми",
+        ru: "Сервис генерации сейчас недоступен",
+    },
+    Message {
+        key: "upstream_error",
+        en: "Upstream request failed",
+        tt: "Генерация хезмәтендә хата",
+        ru: "Ошибка сервиса генерации",
+    },
+    Message {
+        key: "internal_error",
+        en: "Internal server error",
+        tt: "Серверның эчке хатасы",
+        ru: "Внутренняя ошибка сервера",
+    },
+    Message {
+        key: "noun.route",
+        en: "Route",
+        tt: "маршрут",
+        ru: "маршрут",
+    },
+    Message {
+        key: "noun.user",
+        en: "User",
+        tt: "кулланучы",
+        ru: "пользователь",
+    },
+    Message {
+        key: "noun.template",
+        en: "Template",
+        tt: "шаблон",
+        ru: "шаблон",
+    },
+    Message {
+        key: "noun.conversation",
+        en: "Conversation",
+        tt: "сөйләшү",
+        ru: "беседа",
+    },
+    Message {
+        key: "noun.assistant_message",
+        en: "Assistant message",
+        tt: "ассистент хәбәре",
+        ru: "сообщение ассистента",
+    },
+    Message {
+        key: "noun.feedback",
+        en: "Feedback",
+        tt: "бәяләмә",
+        ru: "отзыв",
+    },
+    Message {
+        key: "noun.flag",
+        en: "Flag",
+        tt: "модерация билгесе",
+        ru: "отметка модерации",
+    },
+    Message {
+        key: "noun.rule",
+        en: "Rule",
+        tt: "модерация кагыйдәсе",
+        ru: "правило модерации",
+    },
+    Message {
+        key: "kind.chat_tokens",
+        en: "chat_tokens",
+        tt: "чат токеннары",
+        ru: "токены чата",
+    },
+    Message {
+        key: "kind.images",
+        en: "images",
+        tt: "рәсемнәр",
+        ru: "изображения",
+    },
+    Message {
+        key: "validation.blank",
+        en: "must not be empty",
+        tt: "буш булырга тиеш түгел",
+        ru: "не должно быть пустым",
+    },
+    Message {
+        key: "validation.zero",
+        en: "must not be zero",
+        tt: "нуль булырга тиеш түгел",
+        ru: "не должно быть нулём",
+    },
+    Message {
+        key: "validation.digit",
+        en: "must contain a digit",
+        tt: "кимендә бер цифр булырга тиеш",
+        ru: "должно содержать хотя бы одну цифру",
+    },
+    Message {
+        key: "validation.uppercase",
+        en: "must contain an uppercase letter",
+        tt: "кимендә бер баш хәреф булырга тиеш",
+        ru: "должно содержать хотя бы одну заглавную букву",
+    },
+    Message {
+        key: "validation.symbol",
+        en: "must contain a symbol",
+        tt: "кимендә бер махсус символ булырга тиеш",
+        ru: "должно содержать хотя бы один спецсимвол",
+    },
+    Message {
+        key: "validation.email",
+        en: "must be a valid email address",
+        tt: "дөрес email адресы булырга тиеш",
+        ru: "должно быть корректным email-адресом",
+    },
+    Message {
+        key: "validation.length_between",
+        en: "must be between {min} and {max} characters long",
+        tt: "озынлыгы {min} белән {max} символ арасында булырга тиеш",
+        ru: "длина должна быть от {min} до {max} символов",
+    },
+    Message {
+        key: "validation.length_min",
+        en: "must be at least {min} characters long",
+        tt: "кимендә {min} символ булырга тиеш",
+        ru: "должно содержать не менее {min} символов",
+    },
+    Message {
+        key: "validation.length_max",
+        en: "must be at most {max} characters long",
+        tt: "иң күбе {max} символ булырга тиеш",
+        ru: "должно содержать не более {max} символов",
+    },
+    Message {
+        key: "validation.range_between",
+        en: "must be between {min} and {max}",
+        tt: "{min} белән {max} арасында булырга тиеш",
+        ru: "должно быть от {min} до {max}",
+    },
+    Message {
+        key: "validation.range_min",
+        en: "must be at least {min}",
+        tt: "кимендә {min} булырга тиеш",
+        ru: "должно быть не меньше {min}",
+    },
+    Message {
+        key: "validation.range_max",
+        en: "must be at most {max}",
+        tt: "иң күбе {max} булырга тиеш",
+        ru: "должно быть не больше {max}",
+    },
+    Message {
+        key: "validation.invalid",
+        en: "is invalid",
+        tt: "дөрес түгел",
+        ru: "недопустимое значение",
+    },
+];
+
+/// The message stored under `key`, or the key itself if the catalog has no
+/// such entry.
+pub fn text(locale: Locale, key: &'static str) -> &'static str {
+    CATALOG
+        .iter()
+        .find(|message| message.key == key)
+        .map_or(key, |message| message.get(locale))
+}
+
+/// Translates an English message returned under `scope` (an error code,
+/// `noun` or `kind`). Text the catalog doesn't know, like parser errors,
+/// falls back to the generic message of the scope outside English.
+pub fn translate<'a>(locale: Locale, scope: &str, english: &'a str) -> Cow<'a, str> {
+    let mut entries = CATALOG.iter().filter(|message| {
+        message
+            .key
+            .strip_prefix(scope)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    });
+
+    if let Some(message) = entries.clone().find(|message| message.en == english) {
+        return Cow::Borrowed(message.get(locale));
+    }
+    if locale == Locale::En {
+        return Cow::Borrowed(english);
+    }
+    match entries.find(|message| message.key == scope) {
+        Some(message) => Cow::Borrowed(message.get(locale)),
+        None => Cow::Borrowed(english),
+    }
+}
+
+/// Locale for a response: the signed-in user's preference, then
+/// `Accept-Language`, then `DEFAULT_LOCALE`.
+pub fn resolve(req: &HttpRequest) -> Locale {
+    if let Some(locale) = req.extensions().get::<Locale>() {
+        return *locale;
+    }
+
+    Locale::from_request(req).unwrap_or_else(|| {
+        req.app_data::<web::Data<AppState>>()
+            .map_or(Locale::En, |data| data.env.default_locale)
+    })
+}
+
+/// Re-renders `ApiError` responses in the request's locale. Handlers and
+/// extractors build errors without access to the request, so this runs
+/// around the whole app. Headers set on the way out, such as cookies, a
+/// reissued access token and the security headers, are kept.
+pub async fn localize_errors<B, F>(response: F) -> Result<ServiceResponse<EitherBody<B>>, Error>
+where
+    B: MessageBody,
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    let response = response.await?;
+    let locale = resolve(response.request());
+
+    let localized = match response.response().error() {
+        Some(error) if locale != Locale::En => error
+            .as_error::<ApiError>()
+            .map(|error| error.render(locale)),
+        _ => None,
+    };
+
+    Ok(match localized {
+        Some(mut localized) => {
+            let headers = localized.headers_mut();
+            for (name, value) in response.headers() {
+                if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+                    headers.append(name.clone(), value.clone());
+                }
+            }
+            response.into_response(localized).map_into_right_body()
+        }
+        None => response.map_into_left_body(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        cookie::Cookie,
+        dev::Service,
+        http::{header::HeaderValue, StatusCode},
+        test::{call_service, init_service, read_body_json, TestRequest},
+        web, App,
+    };
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn localized_errors_keep_headers_from_inner_layers() {
+        let app = init_service(
+            App::new()
+                .route(
+                    "/",
+                    web::get().to(|| async { Err::<String, _>(ApiError::NotFound("Route")) }),
+                )
+                .wrap_fn(|req, srv| {
+                    let response = srv.call(req);
+                    async {
+                        let mut response = response.await?;
+                        response
+                            .response_mut()
+                            .add_cookie(&Cookie::new("logged_in", "true"))?;
+                        response
+                            .headers_mut()
+                            .insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+                        Ok(response)
+                    }
+                })
+                .wrap_fn(|req, srv| localize_errors(srv.call(req))),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .insert_header(("Accept-Language", "ru"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(header::X_FRAME_OPTIONS).unwrap(),
+            "DENY"
+        );
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let cookies: Vec<_> = response.response().cookies().collect();
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].name(), "logged_in");
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["message"], "Не найдено: маршрут");
+    }
+}
@@ -1,153 +1,346 @@
-use actix_web::cookie::{time::Duration as ActixWebDuration, Cookie};
-use actix_web::error::{ErrorInternalServerError, ErrorUnauthorized};
-use actix_web::{dev::Payload, Error as ActixWebError};
-use actix_web::{http, web, FromRequest, HttpRequest};
-use redis::AsyncCommands;
+use std::future::Future;
+
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Payload, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{http, web, Error, FromRequest, HttpMessage, HttpRequest};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::model::User;
-use crate::token;
+use crate::dpop;
+use crate::error::ApiError;
+use crate::i18n::Locale;
+use crate::model::UserProfile;
+use crate::profile;
+use crate::token::{self, ProfileClaims, TokenSubject};
 use crate::AppState;
 
-#[derive(Debug, Serialize)]
-struct ErrorResponse {
-    status: String,
-    message: String,
-}
+/// Carries an access token reissued during the request; see `return_reissued`.
+pub const ACCESS_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-access-token");
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JwtMiddleware {
-    pub user: User,
+    pub user: UserProfile,
     pub access_token_uuid: Uuid,
-    pub new_access_token: Option<String>,
+    /// Shared by every token of one login; `None` for older tokens.
+    pub session_id: Option<Uuid>,
+    /// Thumbprint of the DPoP key the access token is bound to.
+    pub key_thumbprint: Option<String>,
+    #[serde(skip)]
+    access_token: String,
 }
 
-impl FromRequest for JwtMiddleware {
-    type Error = ActixWebError;
-    type Future = futures::future::BoxFuture<'static, Result<Self, Self::Error>>;
+/// Authenticates from the access token's claims alone, without loading the
+/// user, for hot endpoints such as the chat gateway. The session is still
+/// checked in the session store, so logout takes effect at once; the locale
+/// for error messages is as of when the token was issued.
+#[derive(Debug)]
+pub struct JwtClaims {
+    pub user_id: Uuid,
+}
 
-    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        let data = match req.app_data::<web::Data<AppState>>() {
-            Some(data) => data.clone(),
-            None => {
-                return Box::pin(async { Err(ErrorInternalServerError("AppState not found")) });
+/// An access token that verified and whose session is still live.
+struct Authenticated {
+    details: token::TokenDetails,
+    access_token: String,
+    from_cookie: bool,
+    user_id: Uuid,
+}
+
+/// Successor of an access token that was close to expiry, stored in the
+/// request extensions until `return_reissued` hands it back.
+#[derive(Clone)]
+struct ReissuedAccessToken {
+    token: String,
+    token_uuid: Uuid,
+    /// `access_token` and `logged_in`, only when the request authenticated
+    /// with the cookie.
+    cookies: Vec<Cookie<'static>>,
+}
+
+impl JwtMiddleware {
+    pub fn require_admin(&self) -> Result<(), ApiError> {
+        if self.user.role != "admin" {
+            return Err(ApiError::AdminRequired);
+        }
+        Ok(())
+    }
+
+    /// For high-value endpoints: a token bound to a key at login must come
+    /// with a fresh `DPoP` proof signed by that key. Unbound tokens pass as
+    /// plain bearer tokens unless `DPOP_REQUIRED` is set.
+    pub async fn require_proof(&self, req: &HttpRequest, data: &AppState) -> Result<(), ApiError> {
+        let bound = match &self.key_thumbprint {
+            Some(bound) => bound,
+            None if data.env.dpop_required => {
+                return Err(ApiError::InvalidProof(
+                    "This request requires an access token bound to a DPoP key".to_string(),
+                ));
             }
+            None => return Ok(()),
         };
 
-        let access_token = req
-            .cookie("access_token")
-            .map(|c| c.value().to_string())
-            .or_else(|| {
-                req.headers()
-                    .get(http::header::AUTHORIZATION)
-                    .and_then(|h| h.to_str().ok())
-                    .and_then(|s| {
-                        if s.starts_with("Bearer ") {
-                            Some(s[7..].to_string())
-                        } else {
-                            None
-                        }
-                    })
-            });
+        let jkt = dpop::verify(
+            req,
+            &data.env,
+            data.session_store.as_ref(),
+            Some(&self.access_token),
+        )
+        .await?;
+        if &jkt != bound {
+            return Err(ApiError::InvalidProof(
+                "DPoP proof is not signed with the key the token is bound to".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Ids to revoke when the session ends: the presented token and any
+    /// successor minted for this request, which is then not handed back.
+    pub fn end_session(&self, req: &HttpRequest) -> Vec<Uuid> {
+        let mut ids = vec![self.access_token_uuid];
+        if let Some(reissued) = req.extensions_mut().remove::<ReissuedAccessToken>() {
+            ids.push(reissued.token_uuid);
+        }
+        ids
+    }
+}
+
+fn user_gone() -> ApiError {
+    ApiError::InvalidToken("The user belonging to this token no longer exists".to_string())
+}
+
+/// Mints the successor of an access token when it expires within
+/// `ACCESS_TOKEN_REISSUE_BEFORE`. The old token keeps working for
+/// `ACCESS_TOKEN_GRACE`, so requests already in flight with it still pass.
+/// The successor carries the current profile, loaded unless `known`.
+async fn reissue(
+    data: &AppState,
+    current: &token::TokenDetails,
+    known: Option<&UserProfile>,
+) -> Result<Option<token::TokenDetails>, ApiError> {
+    let reissue_before = data.env.access_token_reissue_before_secs as i64;
+    let remaining = current.expires_in - chrono::Utc::now().timestamp();
+    if reissue_before == 0 || remaining > reissue_before {
+        return Ok(None);
+    }
+    let store = data.session_store.as_ref();
+    let grace = data.env.access_token_grace_secs;
+    if !token::claim_reissue(store, current.token_uuid, grace).await? {
+        return Ok(None);
+    }
+
+    let loaded;
+    let user = match known {
+        Some(user) => user,
+        None => {
+            loaded = profile::load(
+                data.users.as_ref(),
+                store,
+                current.user_id,
+                data.env.user_cache_ttl_secs,
+            )
+            .await?
+            .ok_or_else(user_gone)?;
+            &loaded
+        }
+    };
+    let details = token::generate_jwt_token(
+        &TokenSubject {
+            user_id: user.id,
+            // Tokens from before sessions had ids start one here.
+            session_id: current.session_id.unwrap_or_else(Uuid::new_v4),
+            key_thumbprint: current.key_thumbprint.as_deref(),
+            profile: Some(user),
+        },
+        data.env.access_token_max_age,
+        data.env.access_token_private_key.clone(),
+        "access",
+        &data.env,
+    )?;
+    token::store_session(store, &details, data.env.access_token_max_age, &data.env).await?;
+    token::retire_session(store, current.token_uuid, grace).await?;
+    Ok(Some(details))
+}
+
+/// Reads the access token from `req` and checks its signature and session;
+/// the part both extractors share.
+async fn authenticate(req: &HttpRequest, data: &AppState) -> Result<Authenticated, ApiError> {
+    // A Bearer header wins over the cookie: `csrf` exempts such
+    // requests, so the cookie must not be what authenticates them.
+    let bearer = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer ").map(|t| t.to_string()));
+    let from_cookie = bearer.is_none();
+    let access_token = bearer
+        .or_else(|| data.env.cookie_policy.read_access_token(req))
+        .ok_or_else(|| {
+            ApiError::Unauthorized("You are not logged in, please provide token".to_string())
+        })?;
+
+    let details = token::verify_jwt_token(
+        data.env.access_token_public_key.clone(),
+        &access_token,
+        &data.env,
+        data.session_store.as_ref(),
+    )
+    .await
+    .map_err(|e| e.into_api_error(|e| ApiError::InvalidToken(format!("Invalid token: {}", e))))?;
+
+    let session_id = details.session_id.unwrap_or(details.token_uuid);
+    tracing::Span::current().record("session_id", session_id.to_string());
 
-        if access_token.is_none() {
-            return Box::pin(async {
-                Err(ErrorUnauthorized(
-                    "You are not logged in, please provide token",
-                ))
+    let user_id = data
+        .session_store
+        .get_session(details.token_uuid)
+        .await?
+        .ok_or_else(|| {
+            ApiError::InvalidToken("Token is invalid or session has expired".to_string())
+        })?;
+    tracing::Span::current().record("user_id", user_id.to_string());
+
+    Ok(Authenticated {
+        details,
+        access_token,
+        from_cookie,
+        user_id,
+    })
+}
+
+impl Authenticated {
+    /// Reissues the token when it is close to expiry and leaves the
+    /// successor in the request for `return_reissued`.
+    async fn reissue(
+        &self,
+        req: &HttpRequest,
+        data: &AppState,
+        known: Option<&UserProfile>,
+    ) -> Result<(), ApiError> {
+        let Some(details) = reissue(data, &self.details, known).await? else {
+            return Ok(());
+        };
+        if let Some(token) = details.token {
+            let policy = &data.env.cookie_policy;
+            let max_age = data.env.access_token_max_age;
+            let cookies = match self.from_cookie {
+                true => vec![
+                    policy.access_token(token.clone(), max_age),
+                    policy.logged_in(max_age),
+                ],
+                false => Vec::new(),
+            };
+            req.extensions_mut().insert(ReissuedAccessToken {
+                token,
+                token_uuid: details.token_uuid,
+                cookies,
             });
         }
+        Ok(())
+    }
+}
 
-        let access_token_str = access_token.unwrap();
+fn app_data(req: &HttpRequest) -> Result<web::Data<AppState>, ApiError> {
+    req.app_data::<web::Data<AppState>>()
+        .cloned()
+        .ok_or_else(|| ApiError::Internal("AppState not found".to_string()))
+}
 
+/// Returns a reissued access token in `X-Access-Token` and, for cookie
+/// sessions, as a new `access_token` cookie. Runs for error responses too:
+/// the old token is already on its way out. `i18n::localize_errors` keeps
+/// both when it re-renders the error.
+pub async fn return_reissued<B, F>(response: F) -> Result<ServiceResponse<B>, Error>
+where
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    let mut response = response.await?;
+    let reissued = response
+        .request()
+        .extensions()
+        .get::<ReissuedAccessToken>()
+        .cloned();
+    let Some(reissued) = reissued else {
+        return Ok(response);
+    };
+    if let Ok(value) = HeaderValue::from_str(&reissued.token) {
+        response.headers_mut().insert(ACCESS_TOKEN_HEADER, value);
+    }
+    for cookie in &reissued.cookies {
+        response.response_mut().add_cookie(cookie)?;
+    }
+    Ok(response)
+}
+
+impl FromRequest for JwtMiddleware {
+    type Error = ApiError;
+    type Future = futures::future::LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
         Box::pin(async move {
-            let mut redis_client = data
-                .redis_client
-                .get_async_connection()
-                .await
-                .map_err(|e| {
-                    ErrorInternalServerError(format!("Could not connect to Redis: {}", e))
-                })?;
-
-            let access_token_details = match token::verify_jwt_token(
-                data.env.access_token_public_key.clone(),
-                &access_token_str,
-                &data.env,
-                &mut redis_client,
+            let data = app_data(&req)?;
+            let auth = authenticate(&req, &data).await?;
+
+            let user = profile::load(
+                data.users.as_ref(),
+                data.session_store.as_ref(),
+                auth.user_id,
+                data.env.user_cache_ttl_secs,
             )
-            .await
-            {
-                Ok(token_details) => token_details,
-                Err(e) => {
-                    return Err(ErrorUnauthorized(format!("Invalid token: {:?}", e)));
-                }
-            };
+            .await?
+            .ok_or_else(user_gone)?;
+            if let Some(locale) = user.locale.as_deref().and_then(Locale::from_tag) {
+                req.extensions_mut().insert(locale);
+            }
+
+            auth.reissue(&req, &data, Some(&user)).await?;
+
+            Ok(JwtMiddleware {
+                user,
+                access_token_uuid: auth.details.token_uuid,
+                session_id: auth.details.session_id,
+                key_thumbprint: auth.details.key_thumbprint,
+                access_token: auth.access_token,
+            })
+        })
+    }
+}
 
-            let access_token_uuid = access_token_details.token_uuid;
-
-            let user_id: String = redis_client
-                .get(access_token_uuid.to_string())
-                .await
-                .map_err(|_| ErrorUnauthorized("Token is invalid or session has expired"))?;
-
-            let user_id_uuid = Uuid::parse_str(&user_id)
-                .map_err(|_| ErrorUnauthorized("Invalid user ID in token"))?;
-
-            let user = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id_uuid)
-                .fetch_optional(&data.db)
-                .await
-                .map_err(|e| {
-                    ErrorInternalServerError(format!("Failed to check user existence: {}", e))
-                })?;
-
-            match user {
-                Some(user) => {
-                    let new_access_token_details = match token::generate_jwt_token(
-                        user.id,
-                        5, 
-                        data.env.access_token_private_key.clone(),
-                        "access",
-                        &data.env,
-                    ) {
-                        Ok(token_details) => token_details,
-                        Err(e) => {
-                            return Err(ErrorInternalServerError(format!(
-                                "Failed to generate new token: {}",
-                                e
-                            )));
-                        }
-                    };
-
-                    let set_result: redis::RedisResult<()> = redis_client
-                        .set_ex(
-                            new_access_token_details.token_uuid.to_string(),
-                            user.id.to_string(),
-                            300,
-                        )
-                        .await;
-
-                    if let Err(e) = set_result {
-                        return Err(ErrorInternalServerError(format!(
-                            "Failed to save new token to Redis: {}",
-                            e
-                        )));
-                    }
-
-                    let _ = redis_client
-                        .del::<&str, i32>(&access_token_uuid.to_string())
-                        .await;
-
-                    Ok(JwtMiddleware {
-                        user,
-                        access_token_uuid: new_access_token_details.token_uuid,
-                        new_access_token: new_access_token_details.token,
-                    })
-                }
-                None => Err(ErrorUnauthorized(
-                    "The user belonging to this token no longer exists",
-                )),
+impl FromRequest for JwtClaims {
+    type Error = ApiError;
+    type Future = futures::future::LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let data = app_data(&req)?;
+            let auth = authenticate(&req, &data).await?;
+
+            let profile = match auth.details.profile.clone() {
+                Some(profile) => profile,
+                // Issued before tokens carried the profile.
+                None => profile::load(
+                    data.users.as_ref(),
+                    data.session_store.as_ref(),
+                    auth.user_id,
+                    data.env.user_cache_ttl_secs,
+                )
+                .await?
+                .as_ref()
+                .map(ProfileClaims::from)
+                .ok_or_else(user_gone)?,
+            };
+            if let Some(locale) = profile.locale.as_deref().and_then(Locale::from_tag) {
+                req.extensions_mut().insert(locale);
             }
+
+            auth.reissue(&req, &data, None).await?;
+
+            Ok(JwtClaims {
+                user_id: auth.user_id,
+            })
         })
     }
 }
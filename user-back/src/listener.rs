@@ -0,0 +1,101 @@
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+
+use crate::config::TlsConfig;
+
+/// Serves whichever certificate was loaded last, so a renewed certificate is
+/// picked up by new connections without a restart.
+#[derive(Debug)]
+pub struct CertResolver {
+    provider: Arc<CryptoProvider>,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn load(tls: &TlsConfig) -> Result<CertResolver, String> {
+        let provider = Arc::new(ring::default_provider());
+        let key = load_certified_key(tls, &provider)?;
+        Ok(CertResolver {
+            provider,
+            key: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    /// A rustls config for the TCP listeners. ALPN is added by actix.
+    pub fn server_config(self: &Arc<Self>) -> Result<ServerConfig, String> {
+        Ok(ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone()))
+    }
+
+    fn reload(&self, tls: &TlsConfig) -> Result<(), String> {
+        let key = load_certified_key(tls, &self.provider)?;
+        *self.key.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+}
+
+fn load_certified_key(tls: &TlsConfig, provider: &CryptoProvider) -> Result<CertifiedKey, String> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{}: {}", tls.cert_file.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!(
+            "{}: no certificates found",
+            tls.cert_file.display()
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_file(&tls.key_file)
+        .map_err(|e| format!("{}: {}", tls.key_file.display(), e))?;
+    CertifiedKey::from_der(certs, key, provider).map_err(|e| e.to_string())
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Polls the certificate and key files and reloads them when either changes.
+/// A pair that fails to load is logged and the previous one stays in use,
+/// which also covers catching the files halfway through a renewal.
+pub async fn run_cert_reloads(resolver: Arc<CertResolver>, tls: TlsConfig) {
+    let mut seen = (modified_at(&tls.cert_file), modified_at(&tls.key_file));
+    let mut interval =
+        actix_web::rt::time::interval(Duration::from_secs(tls.reload_interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        let current = (modified_at(&tls.cert_file), modified_at(&tls.key_file));
+        if current == seen {
+            continue;
+        }
+        match resolver.reload(&tls) {
+            Ok(()) => {
+                seen = current;
+                tracing::info!(cert = %tls.cert_file.display(), "TLS сертификат обновлён");
+            }
+            Err(e) => tracing::error!(error = %e, "не удалось обновить TLS сертификат"),
+        }
+    }
+}
+
+/// Lets nginx, running as another user in the same group, connect.
+pub fn set_socket_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
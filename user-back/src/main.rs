@@ -1,33 +1,89 @@
 mod config;
+mod conversation;
+mod cookies;
+mod cors;
+mod csrf;
+mod dpop;
+mod error;
+mod feedback;
+mod gateway;
 mod handler;
+mod health;
+mod i18n;
 mod jwt_auth;
+mod listener;
+mod metrics;
 mod model;
+mod moderation;
+mod openapi;
+mod profile;
+mod quota;
+mod repository;
 mod response;
+mod security_headers;
+mod session_store;
+mod telemetry;
+mod template;
 mod token;
+mod upstream;
+mod validation;
 
-use actix_cors::Cors;
-use actix_web::middleware::Logger;
-use actix_web::{http::header, web, App, HttpServer};
-use config::Config;
+use actix_web::{dev::Service, web, App, HttpServer};
+use config::{CliArgs, Config, ConfigSource, SessionStoreKind, TelemetryConfig};
 use dotenv::dotenv;
-use redis::Client;
+use health::SystemStats;
+use listener::CertResolver;
+use moderation::Moderator;
+use repository::{PgUserRepository, UserRepository};
+use session_store::{MemorySessionStore, RedisSessionStore, SessionStore};
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use std::sync::Arc;
+use upstream::UpstreamPool;
 
 pub struct AppState {
     db: Pool<Postgres>,
+    users: Arc<dyn UserRepository>,
     env: Config,
-    redis_client: Client,
+    session_store: Arc<dyn SessionStore>,
+    http_client: reqwest::Client,
+    llm_pool: Arc<UpstreamPool>,
+    diffusion_pool: Arc<UpstreamPool>,
+    moderator: Arc<Moderator>,
+    system_stats: Arc<SystemStats>,
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var("RUST_LOG", "actix_web=info");
-    }
     dotenv().ok();
-    env_logger::init();
+    let args = match CliArgs::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let source = ConfigSource::load(args.config_file.as_deref());
+    let _telemetry = telemetry::init(&TelemetryConfig::from_source(&source));
 
-    let config = Config::init();
+    let config = match Config::from_source(&source) {
+        Ok(config) => config,
+        Err(errors) => {
+            for error in &errors {
+                tracing::error!("{}", error);
+            }
+            tracing::error!(count = errors.len(), "конфигурация содержит ошибки");
+            std::process::exit(1);
+        }
+    };
+    if args.check_config {
+        tracing::info!(
+            file = source.file_name().unwrap_or("-"),
+            "конфигурация в порядке"
+        );
+        return Ok(());
+    }
+    metrics::init();
 
     let pool = match PgPoolOptions::new()
         .max_connections(10)
@@ -35,49 +91,180 @@ async fn main() -> std::io::Result<()> {
         .await
     {
         Ok(pool) => {
-            println!("база данных подключена");
+            tracing::info!("база данных подключена");
             pool
         }
         Err(err) => {
-            println!("ошибка подклзючения к бд: {}", err);
+            tracing::error!(error = %err, "ошибка подклзючения к бд");
             std::process::exit(1);
         }
     };
 
-    let redis_client = match Client::open(config.redis_url.to_owned()) {
-        Ok(client) => {
-            println!("редис рабоатет");
-            client
+    let users: Arc<dyn UserRepository> = Arc::new(PgUserRepository::new(pool.clone()));
+
+    let session_store: Arc<dyn SessionStore> = match config.session_store {
+        SessionStoreKind::Redis => match redis::Client::open(config.redis_url.to_owned()) {
+            Ok(client) => {
+                tracing::info!("редис рабоатет");
+                Arc::new(RedisSessionStore::new(client))
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "ошибка редиса");
+                std::process::exit(1);
+            }
+        },
+        SessionStoreKind::Memory => {
+            tracing::warn!("сессии хранятся в памяти процесса");
+            Arc::new(MemorySessionStore::new())
         }
+    };
+
+    let http_client = match reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(config.upstream_timeout_secs))
+        .build()
+    {
+        Ok(client) => client,
         Err(e) => {
-            println!("ошибка редиса: {}", e);
+            tracing::error!(error = %e, "ошибка http клиента");
             std::process::exit(1);
         }
     };
 
-    println!("🚀 Server started successfully");
-
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin(&config.client_origin)
-            .allowed_methods(vec!["GET", "POST"])
-            .allowed_headers(vec![
-                header::CONTENT_TYPE,
-                header::AUTHORIZATION,
-                header::ACCEPT,
-            ])
-            .supports_credentials();
+    let llm_pool = Arc::new(UpstreamPool::new(
+        "llm",
+        &config.llm_upstreams,
+        &config.llm_probe_paths,
+        config.upstream_failure_threshold,
+        config.upstream_cooldown_secs,
+    ));
+    let diffusion_pool = Arc::new(UpstreamPool::new(
+        "diffusion",
+        &config.diffusion_upstreams,
+        &config.diffusion_probe_paths,
+        config.upstream_failure_threshold,
+        config.upstream_cooldown_secs,
+    ));
+
+    actix_web::rt::spawn(upstream::run_probes(
+        vec![llm_pool.clone(), diffusion_pool.clone()],
+        http_client.clone(),
+        config.upstream_probe_interval_secs,
+    ));
+
+    let moderator = Arc::new(Moderator::new(
+        config.moderation_api_url.clone(),
+        config.moderation_fail_closed,
+    ));
+    match moderator.reload(&pool).await {
+        Ok(count) => tracing::info!(count, "правил модерации загружено"),
+        Err(e) => {
+            tracing::error!(error = %e, "ошибка загрузки правил модерации");
+            std::process::exit(1);
+        }
+    }
+
+    actix_web::rt::spawn(moderation::run_reloads(
+        moderator.clone(),
+        pool.clone(),
+        config.moderation_reload_secs,
+    ));
+
+    let system_stats = Arc::new(SystemStats::default());
+    actix_web::rt::spawn(health::run_system_refresh(
+        system_stats.clone(),
+        config.system_stats_interval_secs,
+    ));
+
+    let tls_config = match &config.tls {
+        Some(tls) => {
+            let resolver = match CertResolver::load(tls) {
+                Ok(resolver) => Arc::new(resolver),
+                Err(e) => {
+                    tracing::error!(error = %e, "ошибка загрузки TLS сертификата");
+                    std::process::exit(1);
+                }
+            };
+            actix_web::rt::spawn(listener::run_cert_reloads(resolver.clone(), tls.clone()));
+            match resolver.server_config() {
+                Ok(tls_config) => Some(tls_config),
+                Err(e) => {
+                    tracing::error!(error = %e, "ошибка настройки TLS");
+                    std::process::exit(1);
+                }
+            }
+        }
+        None if config.https_only => {
+            tracing::info!("TLS не настроен, HTTPS обеспечивает прокси");
+            None
+        }
+        None => None,
+    };
+
+    let listener_config = config.clone();
+    let mut server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(AppState {
                 db: pool.clone(),
+                users: users.clone(),
                 env: config.clone(),
-                redis_client: redis_client.clone(),
+                session_store: session_store.clone(),
+                http_client: http_client.clone(),
+                llm_pool: llm_pool.clone(),
+                diffusion_pool: diffusion_pool.clone(),
+                moderator: moderator.clone(),
+                system_stats: system_stats.clone(),
             }))
-            .configure(handler::config)
-            .wrap(cors)
-            .wrap(Logger::default())
+            .app_data(web::JsonConfig::default().error_handler(error::json_error_handler))
+            .app_data(web::QueryConfig::default().error_handler(error::query_error_handler))
+            .app_data(web::PathConfig::default().error_handler(error::path_error_handler))
+            .service(
+                web::scope("/api")
+                    .configure(handler::config)
+                    .wrap(cors::api(&config.cors)),
+            )
+            .service(
+                web::scope("")
+                    .configure(openapi::config)
+                    .configure(health::config)
+                    .configure(metrics::config)
+                    .wrap(cors::root(&config.cors)),
+            )
+            .default_service(web::route().to(error::not_found_handler))
+            .wrap_fn(|req, srv| csrf::protect(csrf::check(req).map(|req| srv.call(req))))
+            .wrap_fn(|req, srv| security_headers::add(jwt_auth::return_reissued(srv.call(req))))
+            .wrap_fn(|req, srv| i18n::localize_errors(srv.call(req)))
+            .wrap_fn(|req, srv| metrics::track(srv.call(req)))
+            .wrap_fn(|req, srv| {
+                let span = telemetry::request_span(&req);
+                telemetry::trace(span, srv.call(req))
+            })
     })
-    .bind(("0.0.0.0", 8000))?
-    .run()
-    .await
+    .shutdown_timeout(listener_config.shutdown_timeout_secs);
+    if let Some(workers) = listener_config.workers {
+        server = server.workers(workers);
+    }
+
+    let port = listener_config.port;
+    for &address in &listener_config.bind_addresses {
+        server = match &tls_config {
+            Some(tls_config) => server.bind_rustls_0_23((address, port), tls_config.clone())?,
+            None => server.bind((address, port))?,
+        };
+        if let Some(http_port) = listener_config.http_port {
+            server = server.bind((address, http_port))?;
+        }
+    }
+    for (address, scheme) in server.addrs_with_scheme() {
+        tracing::info!(%address, scheme, "слушаем");
+    }
+    if let Some(path) = &listener_config.unix_socket {
+        server = server.bind_uds(path)?;
+        listener::set_socket_mode(path, listener_config.unix_socket_mode)?;
+        tracing::info!(path = %path.display(), "слушаем unix сокет");
+    }
+    tracing::info!("🚀 Server started successfully");
+
+    server.run().await?;
+    tracing::info!("сервер остановлен");
+    Ok(())
 }
@@ -0,0 +1,233 @@
+use std::future::Future;
+use std::time::Instant;
+
+use actix_web::{dev::ServiceResponse, get, http::header, web, Error, HttpRequest, HttpResponse};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::AppState;
+
+/// Every collector below is registered here; `/metrics` renders only this
+/// registry.
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(collector: T) -> T {
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("metric registered twice");
+    collector
+}
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            ),
+            &["method", "route"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static LOGINS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("auth_logins_total", "Login attempts by result"),
+            &["result"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static TOKENS_ISSUED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("auth_tokens_issued_total", "JWTs issued by typ"),
+            &["typ"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static TOKENS_VERIFIED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "auth_tokens_verified_total",
+                "JWT verifications by typ and result; typ is unknown when the token did not decode",
+            ),
+            &["typ", "result"],
+        )
+        .unwrap(),
+    )
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Postgres pool connections by state"),
+            &["state"],
+        )
+        .unwrap(),
+    )
+});
+
+static DB_POOL_MAX_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::new(
+            "db_pool_max_connections",
+            "Configured size of the Postgres pool",
+        )
+        .unwrap(),
+    )
+});
+
+static REDIS_UP: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::new(
+            "redis_up",
+            "Whether the session store answered PING during the scrape",
+        )
+        .unwrap(),
+    )
+});
+
+static REDIS_CONNECT_DURATION: Lazy<prometheus::Histogram> = Lazy::new(|| {
+    register(
+        prometheus::Histogram::with_opts(HistogramOpts::new(
+            "redis_connect_duration_seconds",
+            "Time to PING the session store during scrapes",
+        ))
+        .unwrap(),
+    )
+});
+
+static ACTIVE_SESSIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::new(
+            "auth_active_sessions",
+            "Sessions with an access or refresh token still live",
+        )
+        .unwrap(),
+    )
+});
+
+/// Registers every collector up front so series show up before their first
+/// event.
+pub fn init() {
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_REQUEST_DURATION);
+    Lazy::force(&LOGINS);
+    Lazy::force(&TOKENS_ISSUED);
+    Lazy::force(&TOKENS_VERIFIED);
+    Lazy::force(&DB_POOL_CONNECTIONS);
+    Lazy::force(&DB_POOL_MAX_CONNECTIONS);
+    Lazy::force(&REDIS_UP);
+    Lazy::force(&REDIS_CONNECT_DURATION);
+    Lazy::force(&ACTIVE_SESSIONS);
+}
+
+/// Records request count and latency labelled by the matched route pattern,
+/// so path ids don't create a series per resource.
+pub async fn track<B, F>(response: F) -> Result<ServiceResponse<B>, Error>
+where
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    let started = Instant::now();
+    let response = response.await?;
+
+    let method = response.request().method().to_string();
+    let route = response
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    HTTP_REQUESTS
+        .with_label_values(&[method.as_str(), route.as_str(), response.status().as_str()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method.as_str(), route.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+
+    Ok(response)
+}
+
+/// Gauges that are cheaper to read on scrape than to keep up to date.
+async fn refresh_gauges(data: &AppState) {
+    let size = data.db.size() as i64;
+    let idle = data.db.num_idle() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["active"])
+        .set(size - idle);
+    DB_POOL_MAX_CONNECTIONS.set(data.db.options().get_max_connections() as i64);
+
+    let started = Instant::now();
+    let up = data.session_store.ping().await.is_ok();
+    REDIS_CONNECT_DURATION.observe(started.elapsed().as_secs_f64());
+    REDIS_UP.set(up as i64);
+    if !up {
+        return;
+    }
+
+    if let Ok(count) = data.session_store.count_sessions().await {
+        ACTIVE_SESSIONS.set(count);
+    }
+}
+
+/// Compares without returning early, so response time doesn't leak how
+/// much of the token matched.
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[get("/metrics")]
+async fn metrics_handler(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Some(expected) = &data.env.metrics_token {
+        let given = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| s.strip_prefix("Bearer "));
+        if !given.is_some_and(|given| token_matches(expected, given)) {
+            return HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .finish();
+        }
+    }
+
+    refresh_gauges(&data).await;
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::error!(error = %e, "ошибка метрик");
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(buffer)
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(metrics_handler);
+}
@@ -1,5 +1,16 @@
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::config::PasswordPolicy;
+use crate::i18n::Locale;
+use crate::validation::{non_zero, not_blank, password_strength};
+
+/// Longest chat message or correction accepted from a client, in characters.
+pub const MAX_MESSAGE_CHARS: u64 = 8000;
+/// Longest template body (system prompt or format string), in characters.
+pub const MAX_TEMPLATE_CHARS: u64 = 10000;
 
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
@@ -15,17 +26,408 @@ pub struct User {
     pub created_at: Option<DateTime<Utc>>,
     #[serde(rename = "updatedAt")]
     pub updated_at: Option<DateTime<Utc>>,
+    pub locale: Option<String>,
+}
+
+/// A user without the password hash: what requests authenticate as and
+/// what `profile` caches in the session store.
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct UserProfile {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub photo: String,
+    pub verified: bool,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub locale: Option<String>,
+}
+
+impl From<&User> for UserProfile {
+    fn from(user: &User) -> Self {
+        UserProfile {
+            id: user.id,
+            name: user.name.clone(),
+            email: user.email.clone(),
+            role: user.role.clone(),
+            photo: user.photo.clone(),
+            verified: user.verified,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            locale: user.locale.clone(),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[validate(context = PasswordPolicy)]
 pub struct RegisterUserSchema {
+    #[validate(custom(function = "not_blank"), length(max = 100))]
     pub name: String,
+    #[validate(email, length(max = 255))]
     pub email: String,
+    #[validate(length(max = 128), custom(function = "password_strength", use_context))]
     pub password: String,
+    pub locale: Option<Locale>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateLocaleSchema {
+    /// `null` clears the preference so `Accept-Language` applies again.
+    pub locale: Option<Locale>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate, ToSchema)]
 pub struct LoginUserSchema {
+    #[validate(length(min = 1, max = 255))]
     pub email: String,
+    #[validate(length(min = 1, max = 128))]
     pub password: String,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageKind {
+    ChatTokens,
+    Images,
+}
+
+impl UsageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsageKind::ChatTokens => "chat_tokens",
+            UsageKind::Images => "images",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct Plan {
+    pub name: String,
+    pub chat_tokens_daily: Option<i64>,
+    pub chat_tokens_monthly: Option<i64>,
+    pub images_daily: Option<i64>,
+    pub images_monthly: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct UserPlan {
+    pub user_id: uuid::Uuid,
+    pub plan: String,
+    pub chat_token_credits: i64,
+    pub image_credits: i64,
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct UsageLedgerEntry {
+    pub id: i64,
+    pub kind: String,
+    pub entry: String,
+    pub amount: i64,
+    pub source: Option<String>,
+    pub note: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ChatRequestSchema {
+    #[validate(custom(function = "not_blank"), length(max = MAX_MESSAGE_CHARS))]
+    pub message: String,
+    pub template_id: Option<uuid::Uuid>,
+    #[validate(length(min = 1, max = 20))]
+    pub language: Option<String>,
+    #[validate(range(min = 1, max = 4096))]
+    pub max_new_tokens: Option<i64>,
+    #[validate(range(min = 0.0, max = 2.0))]
+    pub temperature: Option<f32>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ImageRequestSchema {
+    #[validate(custom(function = "not_blank"), length(max = 1000))]
+    pub prompt: String,
+    #[validate(length(max = 1000))]
+    pub negative_prompt: Option<String>,
+    #[validate(range(min = 1, max = 150))]
+    pub num_inference_steps: Option<i64>,
+    #[validate(range(min = 0.0, max = 30.0))]
+    pub guidance_scale: Option<f32>,
+    #[validate(range(min = 64, max = 2048))]
+    pub width: Option<i64>,
+    #[validate(range(min = 64, max = 2048))]
+    pub height: Option<i64>,
+    #[validate(range(min = 0))]
+    pub seed: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct GrantCreditsSchema {
+    pub kind: UsageKind,
+    #[validate(custom(function = "non_zero"))]
+    pub amount: i64,
+    #[validate(length(max = 500))]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ChangePlanSchema {
+    #[validate(length(min = 1, max = 50))]
+    pub plan: String,
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct PromptTemplate {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub language: String,
+    pub persona: String,
+    pub system_prompt: String,
+    pub prompt_format: String,
+    pub history_format: String,
+    pub temperature: Option<f32>,
+    pub max_new_tokens: Option<i32>,
+    pub is_default: bool,
+    pub active: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct Conversation {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub template_id: Option<uuid::Uuid>,
+    pub title: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct ConversationMessage {
+    pub id: uuid::Uuid,
+    pub conversation_id: uuid::Uuid,
+    pub role: String,
+    pub content: String,
+    pub template_id: Option<uuid::Uuid>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CreateTemplateSchema {
+    #[validate(custom(function = "not_blank"), length(max = 100))]
+    pub name: String,
+    #[validate(length(min = 1, max = 20))]
+    pub language: Option<String>,
+    #[validate(length(max = 255))]
+    pub persona: Option<String>,
+    #[validate(length(max = MAX_TEMPLATE_CHARS))]
+    pub system_prompt: Option<String>,
+    #[validate(length(max = MAX_TEMPLATE_CHARS))]
+    pub prompt_format: Option<String>,
+    #[validate(length(max = MAX_TEMPLATE_CHARS))]
+    pub history_format: Option<String>,
+    #[validate(range(min = 0.0, max = 2.0))]
+    pub temperature: Option<f32>,
+    #[validate(range(min = 1, max = 4096))]
+    pub max_new_tokens: Option<i32>,
+    pub is_default: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct UpdateTemplateSchema {
+    #[validate(custom(function = "not_blank"), length(max = 100))]
+    pub name: Option<String>,
+    #[validate(length(min = 1, max = 20))]
+    pub language: Option<String>,
+    #[validate(length(max = 255))]
+    pub persona: Option<String>,
+    #[validate(length(max = MAX_TEMPLATE_CHARS))]
+    pub system_prompt: Option<String>,
+    #[validate(length(max = MAX_TEMPLATE_CHARS))]
+    pub prompt_format: Option<String>,
+    #[validate(length(max = MAX_TEMPLATE_CHARS))]
+    pub history_format: Option<String>,
+    #[validate(range(min = 0.0, max = 2.0))]
+    pub temperature: Option<f32>,
+    #[validate(range(min = 1, max = 4096))]
+    pub max_new_tokens: Option<i32>,
+    pub is_default: Option<bool>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CreateConversationSchema {
+    #[validate(length(max = 255))]
+    pub title: Option<String>,
+    pub template_id: Option<uuid::Uuid>,
+    #[validate(length(min = 1, max = 20))]
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct UpdateConversationSchema {
+    #[validate(length(max = 255))]
+    pub title: Option<String>,
+    pub template_id: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ConversationMessageSchema {
+    #[validate(custom(function = "not_blank"), length(max = MAX_MESSAGE_CHARS))]
+    pub message: String,
+    #[validate(range(min = 1, max = 4096))]
+    pub max_new_tokens: Option<i64>,
+    #[validate(range(min = 0.0, max = 2.0))]
+    pub temperature: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Rating {
+    Up,
+    Down,
+}
+
+impl Rating {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rating::Up => "up",
+            Rating::Down => "down",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct MessageFeedback {
+    pub id: uuid::Uuid,
+    pub message_id: uuid::Uuid,
+    pub rating: String,
+    pub correction: Option<String>,
+    pub consent: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct FeedbackSchema {
+    pub rating: Rating,
+    #[validate(length(max = MAX_MESSAGE_CHARS))]
+    pub correction: Option<String>,
+    pub consent: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedbackExportQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub rating: Option<Rating>,
+    pub template_id: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Allow,
+    Flag,
+    Block,
+}
+
+impl ModerationAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationAction::Allow => "allow",
+            ModerationAction::Flag => "flag",
+            ModerationAction::Block => "block",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ModerationAction> {
+        match value {
+            "allow" => Some(ModerationAction::Allow),
+            "flag" => Some(ModerationAction::Flag),
+            "block" => Some(ModerationAction::Block),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    Pending,
+    Confirmed,
+    Dismissed,
+}
+
+impl ReviewStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewStatus::Pending => "pending",
+            ReviewStatus::Confirmed => "confirmed",
+            ReviewStatus::Dismissed => "dismissed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct ModerationRule {
+    pub id: uuid::Uuid,
+    pub pattern: String,
+    pub is_regex: bool,
+    pub language: String,
+    pub action: String,
+    pub active: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone, ToSchema)]
+pub struct ModerationFlag {
+    pub id: uuid::Uuid,
+    pub user_id: Option<uuid::Uuid>,
+    pub kind: String,
+    pub content: String,
+    pub action: String,
+    pub source: String,
+    pub reason: Option<String>,
+    pub status: String,
+    pub review_note: Option<String>,
+    pub reviewed_by: Option<uuid::Uuid>,
+    #[serde(rename = "reviewedAt")]
+    pub reviewed_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CreateModerationRuleSchema {
+    #[validate(custom(function = "not_blank"), length(max = 500))]
+    pub pattern: String,
+    pub is_regex: Option<bool>,
+    #[validate(length(min = 1, max = 20))]
+    pub language: Option<String>,
+    pub action: ModerationAction,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ModerationFlagQuery {
+    pub status: Option<ReviewStatus>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ReviewFlagSchema {
+    pub status: ReviewStatus,
+    #[validate(length(max = 1000))]
+    pub note: Option<String>,
+}
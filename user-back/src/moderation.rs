@@ -0,0 +1,538 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::{
+    error::{ApiError, ErrorEnvelope},
+    jwt_auth,
+    model::{
+        CreateModerationRuleSchema, ModerationAction, ModerationFlag, ModerationFlagQuery,
+        ModerationRule, ReviewFlagSchema, ReviewStatus,
+    },
+    AppState,
+};
+
+use actix_web::{delete, get, post, web, HttpResponse};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+use validator::Validate;
+
+const MODERATION_API_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ContentKind {
+    ChatPrompt,
+    ChatOutput,
+    ImagePrompt,
+}
+
+impl ContentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentKind::ChatPrompt => "chat_prompt",
+            ContentKind::ChatOutput => "chat_output",
+            ContentKind::ImagePrompt => "image_prompt",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Verdict {
+    pub action: ModerationAction,
+    pub source: &'static str,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExternalRequest<'a> {
+    text: &'a str,
+    kind: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExternalVerdict {
+    action: String,
+    reason: Option<String>,
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    regex: Regex,
+    pattern: String,
+    language: String,
+    action: ModerationAction,
+}
+
+/// Words are matched case-insensitively on word boundaries; regex rules are
+/// used as written but also case-insensitive.
+fn compile(pattern: &str, is_regex: bool) -> Result<Regex, regex::Error> {
+    if is_regex {
+        Regex::new(&format!("(?iu){}", pattern))
+    } else {
+        Regex::new(&format!(r"(?iu)\b{}\b", regex::escape(pattern)))
+    }
+}
+
+/// Blocklist held in memory and refreshed from `moderation_rules`, plus the
+/// optional external moderation upstream.
+#[derive(Debug)]
+pub struct Moderator {
+    rules: RwLock<Vec<CompiledRule>>,
+    api_url: Option<String>,
+    fail_closed: bool,
+}
+
+impl Moderator {
+    pub fn new(api_url: Option<String>, fail_closed: bool) -> Moderator {
+        Moderator {
+            rules: RwLock::new(Vec::new()),
+            api_url,
+            fail_closed,
+        }
+    }
+
+    pub async fn reload(&self, db: &Pool<Postgres>) -> Result<usize, sqlx::Error> {
+        let rules = sqlx::query_as!(
+            ModerationRule,
+            "SELECT id, pattern, is_regex, language, action, active, created_at FROM moderation_rules WHERE active"
+        )
+        .fetch_all(db)
+        .await?;
+
+        let compiled: Vec<CompiledRule> = rules
+            .into_iter()
+            .filter_map(|rule| {
+                let action = ModerationAction::parse(&rule.action)?;
+                match compile(&rule.pattern, rule.is_regex) {
+                    Ok(regex) => Some(CompiledRule {
+                        regex,
+                        pattern: rule.pattern,
+                        language: rule.language,
+                        action,
+                    }),
+                    Err(e) => {
+                        tracing::warn!(rule_id = %rule.id, error = %e, "правило модерации пропущено");
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let count = compiled.len();
+        *self.rules.write().unwrap() = compiled;
+        Ok(count)
+    }
+
+    /// An allow rule overrides every block and flag rule, so admins can
+    /// clear known false positives without deactivating the rule behind them.
+    fn match_blocklist(&self, text: &str) -> Option<Verdict> {
+        let rules = self.rules.read().unwrap();
+        let matching = || rules.iter().filter(|rule| rule.regex.is_match(text));
+        if let Some(rule) = matching().find(|rule| rule.action == ModerationAction::Allow) {
+            return Some(Verdict {
+                action: ModerationAction::Allow,
+                source: "allowlist",
+                reason: Some(format!("{} ({})", rule.pattern, rule.language)),
+            });
+        }
+        matching()
+            .max_by_key(|rule| rule.action)
+            .map(|rule| Verdict {
+                action: rule.action,
+                source: "blocklist",
+                reason: Some(format!("{} ({})", rule.pattern, rule.language)),
+            })
+    }
+
+    async fn ask_external(
+        &self,
+        client: &reqwest::Client,
+        api_url: &str,
+        kind: ContentKind,
+        text: &str,
+    ) -> Verdict {
+        let result = client
+            .post(api_url)
+            .timeout(Duration::from_secs(MODERATION_API_TIMEOUT_SECS))
+            .json(&ExternalRequest {
+                text,
+                kind: kind.as_str(),
+            })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        let verdict = match result {
+            Ok(response) => response
+                .json::<ExternalVerdict>()
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match verdict {
+            Ok(verdict) => Verdict {
+                action: ModerationAction::parse(&verdict.action).unwrap_or(ModerationAction::Flag),
+                source: "external",
+                reason: verdict.reason,
+            },
+            Err(e) => {
+                tracing::warn!(error = %e, "сервис модерации недоступен");
+                Verdict {
+                    action: if self.fail_closed {
+                        ModerationAction::Block
+                    } else {
+                        ModerationAction::Allow
+                    },
+                    source: "external",
+                    reason: Some(format!("moderation service unavailable: {}", e)),
+                }
+            }
+        }
+    }
+
+    /// The strictest verdict of the blocklist and the external upstream. The
+    /// upstream is not consulted once the blocklist already blocks, or when
+    /// an allow rule matched.
+    pub async fn evaluate(
+        &self,
+        client: &reqwest::Client,
+        kind: ContentKind,
+        text: &str,
+    ) -> Verdict {
+        let local = self.match_blocklist(text).unwrap_or(Verdict {
+            action: ModerationAction::Allow,
+            source: "blocklist",
+            reason: None,
+        });
+
+        let api_url = match &self.api_url {
+            Some(api_url)
+                if local.action != ModerationAction::Block && local.source != "allowlist" =>
+            {
+                api_url
+            }
+            _ => return local,
+        };
+
+        let external = self.ask_external(client, api_url, kind, text).await;
+        if external.action > local.action {
+            external
+        } else {
+            local
+        }
+    }
+}
+
+/// Runs `text` through moderation, queues anything that isn't allowed for
+/// review and rejects blocked content.
+pub async fn check(
+    data: &AppState,
+    user_id: Uuid,
+    kind: ContentKind,
+    text: &str,
+) -> Result<(), ApiError> {
+    let verdict = data.moderator.evaluate(&data.http_client, kind, text).await;
+
+    if verdict.action == ModerationAction::Allow {
+        return Ok(());
+    }
+
+    let insert_result = sqlx::query!(
+        "INSERT INTO moderation_flags (user_id, kind, content, action, source, reason) VALUES ($1, $2, $3, $4, $5, $6)",
+        user_id,
+        kind.as_str(),
+        text,
+        verdict.action.as_str(),
+        verdict.source,
+        verdict.reason
+    )
+    .execute(&data.db)
+    .await;
+
+    if let Err(e) = insert_result {
+        tracing::error!(error = ?e, "не удалось сохранить отметку модерации");
+    }
+
+    if verdict.action == ModerationAction::Block {
+        return Err(ApiError::ContentRejected);
+    }
+
+    Ok(())
+}
+
+/// Periodically reloads the blocklist so rule changes made through another
+/// instance are picked up.
+pub async fn run_reloads(moderator: Arc<Moderator>, db: Pool<Postgres>, interval_secs: u64) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        if let Err(e) = moderator.reload(&db).await {
+            tracing::error!(error = ?e, "не удалось обновить правила модерации");
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "moderation",
+    responses(
+        (status = 200, description = "`data.rules`: all `ModerationRule`s", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[get("/admins/moderation/rules")]
+async fn list_rules_handler(
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    let rules = sqlx::query_as!(
+        ModerationRule,
+        "SELECT id, pattern, is_regex, language, action, active, created_at FROM moderation_rules ORDER BY language, created_at"
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"rules": rules}
+    })))
+}
+
+#[utoipa::path(
+    tag = "moderation",
+    responses(
+        (status = 201, description = "`data.rule`: the created `ModerationRule`", body = Object),
+        (status = 400, description = "Invalid pattern", body = ErrorEnvelope),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+        (status = 422, description = "Request validation failed", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[post("/admins/moderation/rules")]
+async fn create_rule_handler(
+    body: web::Json<CreateModerationRuleSchema>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    body.validate()?;
+
+    let pattern = body.pattern.trim();
+    let is_regex = body.is_regex.unwrap_or(false);
+    compile(pattern, is_regex).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+    let rule = sqlx::query_as!(
+        ModerationRule,
+        "INSERT INTO moderation_rules (pattern, is_regex, language, action, created_by) VALUES ($1, $2, $3, $4, $5)
+         RETURNING id, pattern, is_regex, language, action, active, created_at",
+        pattern,
+        is_regex,
+        body.language.as_deref().unwrap_or("any").to_lowercase(),
+        body.action.as_str(),
+        jwt.user.id
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    if let Err(e) = data.moderator.reload(&data.db).await {
+        tracing::error!(error = ?e, "не удалось обновить правила модерации");
+    }
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "status": "success",
+        "data": {"rule": rule}
+    })))
+}
+
+#[utoipa::path(
+    tag = "moderation",
+    params(
+        ("id" = Uuid, Path, description = "Rule id"),
+    ),
+    responses(
+        (status = 200, description = "Rule deactivated", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+        (status = 404, description = "Rule not found", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[delete("/admins/moderation/rules/{id}")]
+async fn deactivate_rule_handler(
+    path: web::Path<Uuid>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    let result = sqlx::query!(
+        "UPDATE moderation_rules SET active = FALSE WHERE id = $1",
+        path.into_inner()
+    )
+    .execute(&data.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Rule"));
+    }
+
+    if let Err(e) = data.moderator.reload(&data.db).await {
+        tracing::error!(error = ?e, "не удалось обновить правила модерации");
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success"
+    })))
+}
+
+#[utoipa::path(
+    tag = "moderation",
+    params(
+        ModerationFlagQuery,
+    ),
+    responses(
+        (status = 200, description = "`data.flags`: matching `ModerationFlag`s", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[get("/admins/moderation/flags")]
+async fn list_flags_handler(
+    query: web::Query<ModerationFlagQuery>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    let status = query.status.unwrap_or(ReviewStatus::Pending);
+    let flags = sqlx::query_as!(
+        ModerationFlag,
+        "SELECT id, user_id, kind, content, action, source, reason, status, review_note, reviewed_by, reviewed_at, created_at
+         FROM moderation_flags WHERE status = $1 ORDER BY created_at DESC LIMIT 200",
+        status.as_str()
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"flags": flags}
+    })))
+}
+
+#[utoipa::path(
+    tag = "moderation",
+    params(
+        ("id" = Uuid, Path, description = "Flag id"),
+    ),
+    responses(
+        (status = 200, description = "`data.flag`: the reviewed `ModerationFlag`", body = Object),
+        (status = 400, description = "Invalid review status", body = ErrorEnvelope),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+        (status = 404, description = "Flag not found", body = ErrorEnvelope),
+        (status = 422, description = "Request validation failed", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[post("/admins/moderation/flags/{id}/review")]
+async fn review_flag_handler(
+    path: web::Path<Uuid>,
+    body: web::Json<ReviewFlagSchema>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    body.validate()?;
+
+    if body.status == ReviewStatus::Pending {
+        return Err(ApiError::BadRequest(
+            "Review status must be confirmed or dismissed".to_string(),
+        ));
+    }
+
+    let flag = sqlx::query_as!(
+        ModerationFlag,
+        "UPDATE moderation_flags SET status = $2, review_note = $3, reviewed_by = $4, reviewed_at = NOW()
+         WHERE id = $1
+         RETURNING id, user_id, kind, content, action, source, reason, status, review_note, reviewed_by, reviewed_at, created_at",
+        path.into_inner(),
+        body.status.as_str(),
+        body.note,
+        jwt.user.id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or(ApiError::NotFound("Flag"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"flag": flag}
+    })))
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(list_rules_handler)
+        .service(create_rule_handler)
+        .service(deactivate_rule_handler)
+        .service(list_flags_handler)
+        .service(review_flag_handler);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moderator(rules: &[(&str, ModerationAction)]) -> Moderator {
+        let moderator = Moderator::new(None, false);
+        *moderator.rules.write().unwrap() = rules
+            .iter()
+            .map(|(pattern, action)| CompiledRule {
+                regex: compile(pattern, false).unwrap(),
+                pattern: pattern.to_string(),
+                language: "any".to_string(),
+                action: *action,
+            })
+            .collect();
+        moderator
+    }
+
+    #[actix_web::test]
+    async fn allow_rules_override_block_and_flag_rules() {
+        let moderator = moderator(&[
+            ("kill", ModerationAction::Block),
+            ("process", ModerationAction::Flag),
+            ("kill the process", ModerationAction::Allow),
+        ]);
+        let client = reqwest::Client::new();
+
+        let verdict = moderator
+            .evaluate(
+                &client,
+                ContentKind::ChatPrompt,
+                "How do I kill the process?",
+            )
+            .await;
+        assert_eq!(verdict.action, ModerationAction::Allow);
+        assert_eq!(verdict.source, "allowlist");
+
+        let verdict = moderator
+            .evaluate(&client, ContentKind::ChatPrompt, "How do I kill a process?")
+            .await;
+        assert_eq!(verdict.action, ModerationAction::Block);
+
+        let verdict = moderator
+            .evaluate(&client, ContentKind::ChatPrompt, "Which process is slow?")
+            .await;
+        assert_eq!(verdict.action, ModerationAction::Flag);
+    }
+}
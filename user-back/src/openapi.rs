@@ -0,0 +1,193 @@
+use actix_web::{get, http::header, web, HttpResponse};
+use utoipa::{
+    openapi::{
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+        Server,
+    },
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{conversation, csrf, feedback, gateway, handler, health, moderation, template};
+
+/// Paths in the handlers are relative to the `/api` scope, so the document
+/// declares it as the server instead of repeating it in every path.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "user-back",
+        description = "Authentication, quotas and the LLM/diffusion gateway. Error responses share the `ErrorEnvelope` body; success bodies are `{status, data}` with `data` described per operation. An access token used close to expiry is replaced: the new one comes back in the `X-Access-Token` header and, for cookie sessions, the `access_token` cookie. Clients that send a `DPoP` proof at login get tokens bound to their key and must send a fresh proof to `/auth/refresh`, `/auth/logout-all` and the admin credit, plan and export endpoints."
+    ),
+    servers((url = "/api")),
+    paths(
+        health::livez_handler,
+        health::readyz_handler,
+        handler::health_checker_handler,
+        handler::register_user_handler,
+        handler::login_user_handler,
+        handler::refresh_access_token_handler,
+        handler::logout_handler,
+        handler::logout_all_handler,
+        csrf::csrf_token_handler,
+        handler::get_me_handler,
+        handler::update_locale_handler,
+        handler::get_admin_handler,
+        handler::get_usage_handler,
+        handler::grant_credits_handler,
+        handler::change_plan_handler,
+        gateway::chat_handler,
+        gateway::generate_image_handler,
+        template::list_templates_handler,
+        template::admin_list_templates_handler,
+        template::create_template_handler,
+        template::update_template_handler,
+        template::deactivate_template_handler,
+        conversation::create_conversation_handler,
+        conversation::list_conversations_handler,
+        conversation::get_conversation_handler,
+        conversation::update_conversation_handler,
+        conversation::delete_conversation_handler,
+        conversation::send_message_handler,
+        feedback::put_feedback_handler,
+        feedback::delete_feedback_handler,
+        feedback::export_feedback_handler,
+        moderation::list_rules_handler,
+        moderation::create_rule_handler,
+        moderation::deactivate_rule_handler,
+        moderation::list_flags_handler,
+        moderation::review_flag_handler,
+    ),
+    components(schemas(
+        crate::model::Plan,
+        crate::model::UserPlan,
+        crate::model::UsageLedgerEntry,
+        crate::model::PromptTemplate,
+        crate::model::Conversation,
+        crate::model::ConversationMessage,
+        crate::model::MessageFeedback,
+        crate::model::ModerationRule,
+        crate::model::ModerationFlag,
+    )),
+    modifiers(&SecuritySchemes, &RootPaths),
+    tags(
+        (name = "health", description = "Service status"),
+        (name = "auth", description = "Registration, login and token refresh"),
+        (name = "users", description = "The signed-in user"),
+        (name = "admin", description = "User plans and credits"),
+        (name = "gateway", description = "Chat and image generation"),
+        (name = "templates", description = "Prompt templates"),
+        (name = "conversations", description = "Stored chat conversations"),
+        (name = "feedback", description = "Ratings on assistant replies"),
+        (name = "moderation", description = "Moderation rules and review queue"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "cookie_auth",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("access_token"))),
+        );
+    }
+}
+
+/// Probes are served outside the `/api` scope.
+struct RootPaths;
+
+impl Modify for RootPaths {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for path in ["/livez", "/readyz"] {
+            if let Some(item) = openapi.paths.paths.get_mut(path) {
+                item.servers = Some(vec![Server::new("/")]);
+            }
+        }
+    }
+}
+
+/// ReDoc is pinned so the page never picks up a release nobody tried.
+const REDOC_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>user-back API</title>
+    <meta charset="utf-8"/>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+  </head>
+  <body>
+    <redoc spec-url="/openapi.json"></redoc>
+    <script src="https://cdn.jsdelivr.net/npm/redoc@2.1.5/bundles/redoc.standalone.js"></script>
+  </body>
+</html>
+"#;
+
+#[get("/redoc")]
+async fn redoc_handler() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(REDOC_PAGE)
+}
+
+/// Swagger UI only matches below `/docs/`, and its relative asset paths
+/// need the trailing slash anyway.
+#[get("/docs")]
+async fn docs_redirect_handler() -> HttpResponse {
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, "/docs/"))
+        .finish()
+}
+
+/// `/openapi.json`, Swagger UI under `/docs` and ReDoc at `/redoc`, at the
+/// paths `nginx/default.conf` proxies to this service.
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(SwaggerUi::new("/docs/{_:.*}").url("/openapi.json", ApiDoc::openapi()))
+        .service(docs_redirect_handler)
+        .service(redoc_handler);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+
+    #[actix_web::test]
+    async fn serves_spec_and_doc_pages() {
+        let app = test::init_service(App::new().configure(super::config)).await;
+
+        let request = test::TestRequest::get().uri("/openapi.json").to_request();
+        let spec: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(spec["servers"][0]["url"], "/api");
+        let register = &spec["paths"]["/auth/register"]["post"];
+        assert_eq!(
+            register["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/RegisterUserSchema"
+        );
+        assert_eq!(
+            register["responses"]["422"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/ErrorEnvelope"
+        );
+        assert!(spec["components"]["securitySchemes"]["bearer_auth"].is_object());
+        assert_eq!(spec["paths"]["/readyz"]["servers"][0]["url"], "/");
+
+        for uri in ["/docs/", "/redoc"] {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
+
+        let request = test::TestRequest::get().uri("/docs").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers().get("location").unwrap(), "/docs/");
+    }
+}
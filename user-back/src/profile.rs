@@ -0,0 +1,58 @@
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::model::UserProfile;
+use crate::repository::UserRepository;
+use crate::session_store::SessionStore;
+
+fn cache_key(user_id: Uuid) -> String {
+    format!("user:{}", user_id)
+}
+
+/// Permissions that come with a role, carried in access tokens so clients
+/// and claim-only checks need not know the role table.
+pub fn permissions(role: &str) -> Vec<String> {
+    let granted: &[&str] = match role {
+        "admin" => &[
+            "plans:manage",
+            "templates:manage",
+            "moderation:manage",
+            "feedback:export",
+        ],
+        _ => &[],
+    };
+    granted.iter().map(|p| p.to_string()).collect()
+}
+
+/// The user's profile from the session store, or from `users` on a miss, in
+/// which case it is cached for `ttl_secs`. `None` when the user no longer
+/// exists.
+pub async fn load(
+    users: &dyn UserRepository,
+    store: &dyn SessionStore,
+    user_id: Uuid,
+    ttl_secs: u64,
+) -> Result<Option<UserProfile>, ApiError> {
+    let key = cache_key(user_id);
+    let cached = store.get_cached(&key).await?;
+    // An entry that no longer parses, e.g. after a field was added, is
+    // simply reloaded.
+    if let Some(profile) = cached.and_then(|json| serde_json::from_str(&json).ok()) {
+        return Ok(Some(profile));
+    }
+
+    let profile = users.find_profile(user_id).await?;
+
+    if let Some(profile) = &profile {
+        let json = serde_json::to_string(profile).map_err(|e| ApiError::Internal(e.to_string()))?;
+        store.put_cached(&key, json, ttl_secs).await?;
+    }
+    Ok(profile)
+}
+
+/// Drops the cached profile; every change to a user row must call this so
+/// the next request sees it.
+pub async fn invalidate(store: &dyn SessionStore, user_id: Uuid) -> Result<(), ApiError> {
+    store.delete_cached(&cache_key(user_id)).await?;
+    Ok(())
+}
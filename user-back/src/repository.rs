@@ -0,0 +1,195 @@
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::i18n::Locale;
+use crate::model::{User, UserProfile};
+
+/// What registration stores; the email is stored lowercased.
+pub struct NewUser<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub password_hash: &'a str,
+    pub locale: Option<Locale>,
+}
+
+/// Access to the `users` table, so handlers can be tested without Postgres.
+/// Emails are matched regardless of case, as they are stored lowercased.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn email_exists(&self, email: &str) -> Result<bool, sqlx::Error>;
+
+    async fn create(&self, user: NewUser<'_>) -> Result<UserProfile, sqlx::Error>;
+
+    /// The user with the password hash, for login.
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, sqlx::Error>;
+
+    async fn find_profile(&self, id: Uuid) -> Result<Option<UserProfile>, sqlx::Error>;
+
+    /// `None` when the user no longer exists.
+    async fn set_locale(
+        &self,
+        id: Uuid,
+        locale: Option<Locale>,
+    ) -> Result<Option<UserProfile>, sqlx::Error>;
+}
+
+pub struct PgUserRepository {
+    db: Pool<Postgres>,
+}
+
+impl PgUserRepository {
+    pub fn new(db: Pool<Postgres>) -> PgUserRepository {
+        PgUserRepository { db }
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgUserRepository {
+    async fn email_exists(&self, email: &str) -> Result<bool, sqlx::Error> {
+        Ok(
+            sqlx::query("SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)")
+                .bind(email.to_lowercase())
+                .fetch_one(&self.db)
+                .await?
+                .get(0),
+        )
+    }
+
+    async fn create(&self, user: NewUser<'_>) -> Result<UserProfile, sqlx::Error> {
+        sqlx::query_as!(
+            UserProfile,
+            "INSERT INTO users (name,email,password,locale) VALUES ($1, $2, $3, $4) RETURNING id, name, email, role, photo, verified, created_at, updated_at, locale",
+            user.name,
+            user.email.to_lowercase(),
+            user.password_hash,
+            user.locale.map(|locale| locale.as_str())
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            "SELECT * FROM users WHERE email = $1",
+            email.to_lowercase()
+        )
+        .fetch_optional(&self.db)
+        .await
+    }
+
+    async fn find_profile(&self, id: Uuid) -> Result<Option<UserProfile>, sqlx::Error> {
+        sqlx::query_as!(
+            UserProfile,
+            "SELECT id, name, email, role, photo, verified, created_at, updated_at, locale FROM users WHERE id = $1",
+            id
+        )
+        .fetch_optional(&self.db)
+        .await
+    }
+
+    async fn set_locale(
+        &self,
+        id: Uuid,
+        locale: Option<Locale>,
+    ) -> Result<Option<UserProfile>, sqlx::Error> {
+        sqlx::query_as!(
+            UserProfile,
+            "UPDATE users SET locale = $2, updated_at = NOW() WHERE id = $1 RETURNING id, name, email, role, photo, verified, created_at, updated_at, locale",
+            id,
+            locale.map(|locale| locale.as_str())
+        )
+        .fetch_optional(&self.db)
+        .await
+    }
+}
+
+/// Keeps users in memory, with the column defaults of the `users` table.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryUserRepository {
+    users: std::sync::Mutex<Vec<User>>,
+}
+
+#[cfg(test)]
+impl MemoryUserRepository {
+    fn users(&self) -> std::sync::MutexGuard<'_, Vec<User>> {
+        self.users.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Changes a role the way an operator would, straight in the table.
+    pub fn set_role(&self, email: &str, role: &str) {
+        if let Some(user) = self.users().iter_mut().find(|user| user.email == email) {
+            user.role = role.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn email_exists(&self, email: &str) -> Result<bool, sqlx::Error> {
+        let email = email.to_lowercase();
+        Ok(self.users().iter().any(|user| user.email == email))
+    }
+
+    async fn create(&self, new: NewUser<'_>) -> Result<UserProfile, sqlx::Error> {
+        let email = new.email.to_lowercase();
+        let mut users = self.users();
+        if users.iter().any(|user| user.email == email) {
+            return Err(sqlx::Error::Protocol(
+                "duplicate key value violates unique constraint \"users_email_key\"".to_string(),
+            ));
+        }
+        let now = chrono::Utc::now();
+        let user = User {
+            id: Uuid::new_v4(),
+            name: new.name.to_string(),
+            email,
+            password: new.password_hash.to_string(),
+            role: "user".to_string(),
+            photo: "default.png".to_string(),
+            verified: false,
+            created_at: Some(now),
+            updated_at: Some(now),
+            locale: new.locale.map(|locale| locale.as_str().to_string()),
+        };
+        let profile = UserProfile::from(&user);
+        users.push(user);
+        Ok(profile)
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, sqlx::Error> {
+        let email = email.to_lowercase();
+        Ok(self
+            .users()
+            .iter()
+            .find(|user| user.email == email)
+            .cloned())
+    }
+
+    async fn find_profile(&self, id: Uuid) -> Result<Option<UserProfile>, sqlx::Error> {
+        Ok(self
+            .users()
+            .iter()
+            .find(|user| user.id == id)
+            .map(UserProfile::from))
+    }
+
+    async fn set_locale(
+        &self,
+        id: Uuid,
+        locale: Option<Locale>,
+    ) -> Result<Option<UserProfile>, sqlx::Error> {
+        Ok(self
+            .users()
+            .iter_mut()
+            .find(|user| user.id == id)
+            .map(|user| {
+                user.locale = locale.map(|locale| locale.as_str().to_string());
+                user.updated_at = Some(chrono::Utc::now());
+                UserProfile::from(&*user)
+            }))
+    }
+}
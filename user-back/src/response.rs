@@ -1,23 +1,25 @@
 use chrono::prelude::*;
 use serde::Serialize;
+use utoipa::ToSchema;
 
 #[allow(non_snake_case)]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct FilteredUser {
     pub id: String,
     pub email: String,
     pub name: String,
     pub role: String,
     pub verified: bool,
-    pub createdAt: DateTime<Utc>,
-    pub updatedAt: DateTime<Utc>,
+    pub createdAt: Option<DateTime<Utc>>,
+    pub updatedAt: Option<DateTime<Utc>>,
+    pub locale: Option<String>,
 }
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct UserData {
     pub user: FilteredUser,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct UserResponse {
     pub status: String,
     pub data: UserData,
@@ -0,0 +1,163 @@
+use std::future::Future;
+
+use actix_web::{
+    dev::ServiceResponse,
+    http::header::{self, HeaderMap, HeaderValue},
+    web, Error,
+};
+
+use crate::{config::SecurityHeaders, AppState};
+
+fn strict_transport_security(headers: &SecurityHeaders) -> Option<HeaderValue> {
+    if headers.hsts_max_age_secs == 0 {
+        return None;
+    }
+    let mut value = format!("max-age={}", headers.hsts_max_age_secs);
+    if headers.hsts_include_subdomains {
+        value.push_str("; includeSubDomains");
+    }
+    if headers.hsts_preload {
+        value.push_str("; preload");
+    }
+    HeaderValue::from_str(&value).ok()
+}
+
+/// Tokens are only ever returned to requests that authenticate or to
+/// responses that set session cookies, so those are the ones that must not
+/// be stored by browsers or proxies.
+fn carries_credentials(request: &HeaderMap, response: &HeaderMap) -> bool {
+    request.contains_key(header::AUTHORIZATION)
+        || request.contains_key(header::COOKIE)
+        || response.contains_key(header::SET_COOKIE)
+}
+
+/// Adds the configured headers to `response`, keeping any a handler set.
+fn apply(
+    headers: &SecurityHeaders,
+    https_only: bool,
+    request: &HeaderMap,
+    response: &mut HeaderMap,
+) {
+    let mut defaults = vec![
+        (
+            header::X_CONTENT_TYPE_OPTIONS,
+            Some(HeaderValue::from_static("nosniff")),
+        ),
+        (
+            header::REFERRER_POLICY,
+            HeaderValue::from_str(&headers.referrer_policy).ok(),
+        ),
+        (
+            header::X_FRAME_OPTIONS,
+            HeaderValue::from_str(&headers.frame_options).ok(),
+        ),
+        (
+            header::CONTENT_SECURITY_POLICY,
+            headers
+                .content_security_policy
+                .as_deref()
+                .and_then(|value| HeaderValue::from_str(value).ok()),
+        ),
+    ];
+    if https_only {
+        defaults.push((
+            header::STRICT_TRANSPORT_SECURITY,
+            strict_transport_security(headers),
+        ));
+    }
+    if carries_credentials(request, response) && !response.contains_key(header::CACHE_CONTROL) {
+        defaults.push((
+            header::CACHE_CONTROL,
+            Some(HeaderValue::from_static("no-store")),
+        ));
+        defaults.push((header::PRAGMA, Some(HeaderValue::from_static("no-cache"))));
+    }
+
+    for (name, value) in defaults {
+        if let (Some(value), false) = (value, response.contains_key(&name)) {
+            response.insert(name, value);
+        }
+    }
+}
+
+pub async fn add<B, F>(response: F) -> Result<ServiceResponse<B>, Error>
+where
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    let mut response = response.await?;
+    let Some(data) = response
+        .request()
+        .app_data::<web::Data<AppState>>()
+        .cloned()
+    else {
+        return Ok(response);
+    };
+    let request = response.request().headers().clone();
+    apply(
+        &data.env.security_headers,
+        data.env.https_only,
+        &request,
+        response.headers_mut(),
+    );
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> SecurityHeaders {
+        SecurityHeaders {
+            hsts_max_age_secs: 31_536_000,
+            hsts_include_subdomains: true,
+            hsts_preload: false,
+            referrer_policy: "no-referrer".to_string(),
+            frame_options: "DENY".to_string(),
+            content_security_policy: None,
+        }
+    }
+
+    #[test]
+    fn adds_defaults_and_hsts_only_over_https() {
+        let mut response = HeaderMap::new();
+        apply(&settings(), false, &HeaderMap::new(), &mut response);
+        assert_eq!(
+            response.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+            "nosniff"
+        );
+        assert_eq!(
+            response.get(header::REFERRER_POLICY).unwrap(),
+            "no-referrer"
+        );
+        assert!(!response.contains_key(header::STRICT_TRANSPORT_SECURITY));
+        assert!(!response.contains_key(header::CACHE_CONTROL));
+
+        let mut response = HeaderMap::new();
+        apply(&settings(), true, &HeaderMap::new(), &mut response);
+        assert_eq!(
+            response.get(header::STRICT_TRANSPORT_SECURITY).unwrap(),
+            "max-age=31536000; includeSubDomains"
+        );
+    }
+
+    #[test]
+    fn marks_token_responses_non_cacheable() {
+        let mut response = HeaderMap::new();
+        response.insert(
+            header::SET_COOKIE,
+            HeaderValue::from_static("access_token=jwt; Path=/"),
+        );
+        apply(&settings(), true, &HeaderMap::new(), &mut response);
+        assert_eq!(response.get(header::CACHE_CONTROL).unwrap(), "no-store");
+
+        let mut request = HeaderMap::new();
+        request.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer jwt"),
+        );
+        let mut response = HeaderMap::new();
+        response.insert(header::CACHE_CONTROL, HeaderValue::from_static("private"));
+        apply(&settings(), true, &request, &mut response);
+        assert_eq!(response.get(header::CACHE_CONTROL).unwrap(), "private");
+    }
+}
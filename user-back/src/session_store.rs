@@ -0,0 +1,804 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::AsyncCommands;
+use uuid::Uuid;
+
+/// Sessions of all users, scored by when their last token runs out, so
+/// counting them doesn't walk the keyspace.
+const LIVE_SESSIONS_KEY: &str = "live_sessions";
+
+// Increments every counter only if the reservation fits in all of their
+// limits. ARGV holds the amount, then a limit and a TTL per key; a negative
+// limit means unlimited.
+const RESERVE_SCRIPT: &str = r#"
+local amount = tonumber(ARGV[1])
+for i, key in ipairs(KEYS) do
+    local limit = tonumber(ARGV[2 * i])
+    local used = tonumber(redis.call('GET', key) or '0')
+    if limit >= 0 and used + amount > limit then
+        return 0
+    end
+end
+for i, key in ipairs(KEYS) do
+    redis.call('INCRBY', key, amount)
+    redis.call('EXPIRE', key, ARGV[2 * i + 1])
+end
+return 1
+"#;
+
+#[derive(Debug)]
+pub struct StoreError(String);
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<redis::RedisError> for StoreError {
+    fn from(e: redis::RedisError) -> Self {
+        StoreError(e.to_string())
+    }
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+/// A usage counter together with the most it may reach.
+pub struct CounterLimit<'a> {
+    pub key: &'a str,
+    /// `None` for unlimited.
+    pub limit: Option<i64>,
+    pub ttl_secs: u64,
+}
+
+/// Short-lived server-side state: live token ids, single-use markers,
+/// cached profiles and usage counters. Every TTL is in seconds.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Names the store in health checks and metrics.
+    fn name(&self) -> &'static str;
+
+    async fn ping(&self) -> StoreResult<()>;
+
+    /// Records `token_uuid` as a live token of `user_id` in session
+    /// `session_id`. The user's index of sessions and the session's index of
+    /// tokens live for `index_ttl_secs`; entries that have run out are
+    /// dropped from both on the way.
+    async fn put_session(
+        &self,
+        token_uuid: Uuid,
+        user_id: Uuid,
+        session_id: Uuid,
+        ttl_secs: u64,
+        index_ttl_secs: u64,
+    ) -> StoreResult<()>;
+
+    /// The user a live session belongs to.
+    async fn get_session(&self, token_uuid: Uuid) -> StoreResult<Option<Uuid>>;
+
+    /// Shortens a session to end `ttl_secs` from now.
+    async fn expire_session(&self, token_uuid: Uuid, ttl_secs: u64) -> StoreResult<()>;
+
+    async fn delete_sessions(&self, token_uuids: &[Uuid]) -> StoreResult<()>;
+
+    /// Deletes every token of one session of the user and returns how many
+    /// were still live.
+    async fn end_session(&self, user_id: Uuid, session_id: Uuid) -> StoreResult<usize>;
+
+    /// Deletes every indexed token of the user and returns how many were
+    /// still live.
+    async fn delete_all_sessions(&self, user_id: Uuid) -> StoreResult<usize>;
+
+    /// Live sessions of all users.
+    async fn count_sessions(&self) -> StoreResult<i64>;
+
+    /// Marks `key` as used for `ttl_secs`; `false` when it already was.
+    /// Backs refresh nonces, DPoP `jti`s and reissue claims.
+    async fn use_once(&self, key: &str, ttl_secs: u64) -> StoreResult<bool>;
+
+    async fn get_cached(&self, key: &str) -> StoreResult<Option<String>>;
+
+    async fn put_cached(&self, key: &str, value: String, ttl_secs: u64) -> StoreResult<()>;
+
+    async fn delete_cached(&self, key: &str) -> StoreResult<()>;
+
+    /// Current values, `0` for counters never incremented.
+    async fn counters(&self, keys: &[&str]) -> StoreResult<Vec<i64>>;
+
+    /// Atomically adds `amount` to every counter if none would go over its
+    /// limit; `false` leaves them all untouched.
+    async fn reserve(&self, counters: &[CounterLimit<'_>], amount: i64) -> StoreResult<bool>;
+
+    /// Adds `delta` to every counter regardless of limits.
+    async fn add_to_counters(&self, keys: &[&str], delta: i64) -> StoreResult<()>;
+}
+
+/// Sessions of a user, scored by when their last token runs out.
+fn sessions_key(user_id: Uuid) -> String {
+    format!("user_sessions:{}", user_id)
+}
+
+/// Tokens of a session, scored by when they run out.
+fn session_tokens_key(session_id: impl std::fmt::Display) -> String {
+    format!("session:{}", session_id)
+}
+
+/// The unsorted index of token ids used before sessions were grouped. It is
+/// only read, and runs out within a refresh token lifetime.
+fn legacy_sessions_key(user_id: Uuid) -> String {
+    format!("sessions:{}", user_id)
+}
+
+fn unix_now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Redis through one multiplexed connection that is shared by all requests
+/// and reconnects on its own. It is opened on first use, so the service
+/// starts, and reports itself degraded, while Redis is down.
+pub struct RedisSessionStore {
+    client: redis::Client,
+    connection: Mutex<Connection>,
+}
+
+#[derive(Default)]
+struct Connection {
+    manager: Option<ConnectionManager>,
+    /// Until when requests fail without trying to connect: while one
+    /// attempt is under way, and for a while after it failed.
+    retry_after: Option<Instant>,
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const CONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+impl RedisSessionStore {
+    pub fn new(client: redis::Client) -> RedisSessionStore {
+        RedisSessionStore {
+            client,
+            connection: Mutex::new(Connection::default()),
+        }
+    }
+
+    /// Connects on first use. Only one request at a time tries to, without
+    /// holding the lock; the rest fail fast until it is done.
+    async fn connection(&self) -> StoreResult<ConnectionManager> {
+        {
+            let mut connection = self.lock_connection();
+            if let Some(manager) = &connection.manager {
+                return Ok(manager.clone());
+            }
+            let now = Instant::now();
+            if connection.retry_after.is_some_and(|at| at > now) {
+                return Err(StoreError(
+                    "Redis is unavailable, retrying shortly".to_string(),
+                ));
+            }
+            connection.retry_after = Some(now + CONNECT_TIMEOUT);
+        }
+
+        // No retries: a request fails fast while Redis is down and one after
+        // the backoff tries again.
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(0)
+            .set_connection_timeout(CONNECT_TIMEOUT)
+            .set_response_timeout(CONNECT_TIMEOUT);
+        let result = self.client.get_connection_manager_with_config(config).await;
+
+        let mut connection = self.lock_connection();
+        match result {
+            Ok(manager) => {
+                connection.retry_after = None;
+                Ok(connection.manager.insert(manager).clone())
+            }
+            Err(e) => {
+                connection.retry_after = Some(Instant::now() + CONNECT_BACKOFF);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn lock_connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn ping(&self) -> StoreResult<()> {
+        let mut conn = self.connection().await?;
+        redis::cmd("PING").query_async::<String>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn put_session(
+        &self,
+        token_uuid: Uuid,
+        user_id: Uuid,
+        session_id: Uuid,
+        ttl_secs: u64,
+        index_ttl_secs: u64,
+    ) -> StoreResult<()> {
+        let mut conn = self.connection().await?;
+        let now = unix_now();
+        let expires_at = now + ttl_secs.max(1) as i64;
+        let index_ttl = index_ttl_secs.max(1) as i64;
+        let index = sessions_key(user_id);
+        let tokens = session_tokens_key(session_id);
+        redis::pipe()
+            .atomic()
+            .set_ex(token_uuid.to_string(), user_id.to_string(), ttl_secs.max(1))
+            .ignore()
+            .zrembyscore(&tokens, "-inf", now)
+            .ignore()
+            .zadd(&tokens, token_uuid.to_string(), expires_at)
+            .ignore()
+            .expire(&tokens, index_ttl)
+            .ignore()
+            .zrembyscore(&index, "-inf", now)
+            .ignore()
+            // GT: a short-lived access token must not pull the session's
+            // end before that of its refresh token.
+            .cmd("ZADD")
+            .arg(&index)
+            .arg("GT")
+            .arg(expires_at)
+            .arg(session_id.to_string())
+            .ignore()
+            .expire(&index, index_ttl)
+            .ignore()
+            .cmd("ZADD")
+            .arg(LIVE_SESSIONS_KEY)
+            .arg("GT")
+            .arg(expires_at)
+            .arg(session_id.to_string())
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_session(&self, token_uuid: Uuid) -> StoreResult<Option<Uuid>> {
+        let mut conn = self.connection().await?;
+        let user_id: Option<String> = conn.get(token_uuid.to_string()).await?;
+        Ok(user_id.and_then(|id| Uuid::parse_str(&id).ok()))
+    }
+
+    async fn expire_session(&self, token_uuid: Uuid, ttl_secs: u64) -> StoreResult<()> {
+        let mut conn = self.connection().await?;
+        conn.expire::<_, ()>(token_uuid.to_string(), ttl_secs as i64)
+            .await?;
+        Ok(())
+    }
+
+    /// The ids stay in their session's index until they would have run
+    /// out, when the next token of the session prunes them.
+    async fn delete_sessions(&self, token_uuids: &[Uuid]) -> StoreResult<()> {
+        let mut conn = self.connection().await?;
+        let ids: Vec<String> = token_uuids.iter().map(Uuid::to_string).collect();
+        conn.del::<_, ()>(&ids).await?;
+        Ok(())
+    }
+
+    async fn end_session(&self, user_id: Uuid, session_id: Uuid) -> StoreResult<usize> {
+        let mut conn = self.connection().await?;
+        let tokens = session_tokens_key(session_id);
+        let token_ids: Vec<String> = conn.zrange(&tokens, 0, -1).await?;
+        let deleted: usize = if token_ids.is_empty() {
+            0
+        } else {
+            conn.del(&token_ids).await?
+        };
+        redis::pipe()
+            .atomic()
+            .del(&tokens)
+            .ignore()
+            .zrem(sessions_key(user_id), session_id.to_string())
+            .ignore()
+            .zrem(LIVE_SESSIONS_KEY, session_id.to_string())
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await?;
+        Ok(deleted)
+    }
+
+    async fn delete_all_sessions(&self, user_id: Uuid) -> StoreResult<usize> {
+        let mut conn = self.connection().await?;
+        let index = sessions_key(user_id);
+        let legacy = legacy_sessions_key(user_id);
+        let (session_ids, mut token_ids): (Vec<String>, Vec<String>) = redis::pipe()
+            .zrange(&index, 0, -1)
+            .smembers(&legacy)
+            .query_async(&mut conn)
+            .await?;
+
+        let mut keys: Vec<String> = session_ids.iter().map(session_tokens_key).collect();
+        if !keys.is_empty() {
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.zrange(key, 0, -1);
+            }
+            let tokens: Vec<Vec<String>> = pipe.query_async(&mut conn).await?;
+            token_ids.extend(tokens.into_iter().flatten());
+            conn.zrem::<_, _, ()>(LIVE_SESSIONS_KEY, &session_ids)
+                .await?;
+        }
+        let deleted: usize = if token_ids.is_empty() {
+            0
+        } else {
+            conn.del(&token_ids).await?
+        };
+
+        keys.push(index);
+        keys.push(legacy);
+        conn.del::<_, ()>(&keys).await?;
+        Ok(deleted)
+    }
+
+    async fn count_sessions(&self) -> StoreResult<i64> {
+        let mut conn = self.connection().await?;
+        let ((), count): ((), i64) = redis::pipe()
+            .atomic()
+            .zrembyscore(LIVE_SESSIONS_KEY, "-inf", unix_now())
+            .zcard(LIVE_SESSIONS_KEY)
+            .query_async(&mut conn)
+            .await?;
+        Ok(count)
+    }
+
+    async fn use_once(&self, key: &str, ttl_secs: u64) -> StoreResult<bool> {
+        let mut conn = self.connection().await?;
+        let first_use: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_secs.max(1))
+            .query_async(&mut conn)
+            .await?;
+        Ok(first_use.is_some())
+    }
+
+    async fn get_cached(&self, key: &str) -> StoreResult<Option<String>> {
+        let mut conn = self.connection().await?;
+        Ok(conn.get(key).await?)
+    }
+
+    async fn put_cached(&self, key: &str, value: String, ttl_secs: u64) -> StoreResult<()> {
+        let mut conn = self.connection().await?;
+        conn.set_ex::<_, _, ()>(key, value, ttl_secs.max(1)).await?;
+        Ok(())
+    }
+
+    async fn delete_cached(&self, key: &str) -> StoreResult<()> {
+        let mut conn = self.connection().await?;
+        conn.del::<_, ()>(key).await?;
+        Ok(())
+    }
+
+    async fn counters(&self, keys: &[&str]) -> StoreResult<Vec<i64>> {
+        let mut conn = self.connection().await?;
+        let values: Vec<Option<i64>> = redis::cmd("MGET").arg(keys).query_async(&mut conn).await?;
+        Ok(values.into_iter().map(|v| v.unwrap_or(0)).collect())
+    }
+
+    async fn reserve(&self, counters: &[CounterLimit<'_>], amount: i64) -> StoreResult<bool> {
+        let mut conn = self.connection().await?;
+        let script = redis::Script::new(RESERVE_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(amount);
+        for counter in counters {
+            invocation
+                .key(counter.key)
+                .arg(counter.limit.unwrap_or(-1))
+                .arg(counter.ttl_secs);
+        }
+        let reserved: i64 = invocation.invoke_async(&mut conn).await?;
+        Ok(reserved == 1)
+    }
+
+    async fn add_to_counters(&self, keys: &[&str], delta: i64) -> StoreResult<()> {
+        let mut conn = self.connection().await?;
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.incr(*key, delta).ignore();
+        }
+        pipe.query_async::<()>(&mut conn).await?;
+        Ok(())
+    }
+}
+
+enum Value {
+    Text(String),
+    Counter(i64),
+    /// Members with when they run out, like a Redis sorted set.
+    Index(HashMap<Uuid, Instant>),
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn live(&self, now: Instant) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    swept_at: Option<Instant>,
+}
+
+impl Entries {
+    fn get(&self, key: &str) -> Option<&Value> {
+        let now = Instant::now();
+        self.map
+            .get(key)
+            .filter(|entry| entry.live(now))
+            .map(|entry| &entry.value)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        let now = Instant::now();
+        self.map.get_mut(key).filter(|entry| entry.live(now))
+    }
+
+    fn counter(&self, key: &str) -> i64 {
+        match self.get(key) {
+            Some(Value::Counter(n)) => *n,
+            _ => 0,
+        }
+    }
+
+    /// Expired entries are skipped on read and dropped here, at most once a
+    /// minute.
+    fn insert(&mut self, key: String, value: Value, ttl_secs: Option<u64>) {
+        let now = Instant::now();
+        if self
+            .swept_at
+            .is_none_or(|at| now - at > Duration::from_secs(60))
+        {
+            self.map.retain(|_, entry| entry.live(now));
+            self.swept_at = Some(now);
+        }
+        let expires_at = ttl_secs.map(|ttl| now + Duration::from_secs(ttl.max(1)));
+        self.map.insert(key, Entry { value, expires_at });
+    }
+
+    fn expire(&mut self, key: &str, ttl_secs: u64) {
+        if let Some(entry) = self.get_mut(key) {
+            entry.expires_at = Some(Instant::now() + Duration::from_secs(ttl_secs));
+        }
+    }
+
+    /// Adds `member` to the index at `key`, keeping the later of its two
+    /// ends, and drops members that have run out.
+    fn index_add(&mut self, key: String, member: Uuid, ends_at: Instant, ttl_secs: Option<u64>) {
+        let now = Instant::now();
+        let mut members = self.take_index(&key);
+        members.retain(|_, at| *at > now);
+        let at = members.entry(member).or_insert(ends_at);
+        *at = (*at).max(ends_at);
+        self.insert(key, Value::Index(members), ttl_secs);
+    }
+
+    /// Removes `members` from the index at `key` and returns how many live
+    /// members are left.
+    fn index_remove(&mut self, key: &str, members: &[Uuid]) -> usize {
+        let now = Instant::now();
+        match self.get_mut(key) {
+            Some(Entry {
+                value: Value::Index(index),
+                ..
+            }) => {
+                index.retain(|member, at| *at > now && !members.contains(member));
+                index.len()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Removes the index at `key` and returns its members.
+    fn take_index(&mut self, key: &str) -> HashMap<Uuid, Instant> {
+        match self.map.remove(key) {
+            Some(Entry {
+                value: Value::Index(members),
+                expires_at,
+            }) if expires_at.is_none_or(|at| at > Instant::now()) => members,
+            _ => HashMap::new(),
+        }
+    }
+}
+
+/// Keeps everything in this process, for development and tests. Sessions
+/// do not survive a restart and are not shared between instances.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    entries: Mutex<Entries>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> MemorySessionStore {
+        MemorySessionStore::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries> {
+        // Nothing panics while holding the lock, but a poisoned map is still
+        // consistent enough to use.
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn ping(&self) -> StoreResult<()> {
+        Ok(())
+    }
+
+    async fn put_session(
+        &self,
+        token_uuid: Uuid,
+        user_id: Uuid,
+        session_id: Uuid,
+        ttl_secs: u64,
+        index_ttl_secs: u64,
+    ) -> StoreResult<()> {
+        let mut entries = self.entries();
+        let ends_at = Instant::now() + Duration::from_secs(ttl_secs.max(1));
+        entries.insert(
+            token_uuid.to_string(),
+            Value::Text(user_id.to_string()),
+            Some(ttl_secs),
+        );
+        entries.index_add(
+            session_tokens_key(session_id),
+            token_uuid,
+            ends_at,
+            Some(index_ttl_secs),
+        );
+        entries.index_add(
+            sessions_key(user_id),
+            session_id,
+            ends_at,
+            Some(index_ttl_secs),
+        );
+        entries.index_add(LIVE_SESSIONS_KEY.to_string(), session_id, ends_at, None);
+        Ok(())
+    }
+
+    async fn get_session(&self, token_uuid: Uuid) -> StoreResult<Option<Uuid>> {
+        Ok(match self.entries().get(&token_uuid.to_string()) {
+            Some(Value::Text(user_id)) => Uuid::parse_str(user_id).ok(),
+            _ => None,
+        })
+    }
+
+    async fn expire_session(&self, token_uuid: Uuid, ttl_secs: u64) -> StoreResult<()> {
+        self.entries().expire(&token_uuid.to_string(), ttl_secs);
+        Ok(())
+    }
+
+    async fn delete_sessions(&self, token_uuids: &[Uuid]) -> StoreResult<()> {
+        let mut entries = self.entries();
+        for token_uuid in token_uuids {
+            entries.map.remove(&token_uuid.to_string());
+        }
+        Ok(())
+    }
+
+    async fn end_session(&self, user_id: Uuid, session_id: Uuid) -> StoreResult<usize> {
+        let mut entries = self.entries();
+        let now = Instant::now();
+        let mut deleted = 0;
+        for token_uuid in entries
+            .take_index(&session_tokens_key(session_id))
+            .into_keys()
+        {
+            if let Some(entry) = entries.map.remove(&token_uuid.to_string()) {
+                deleted += entry.live(now) as usize;
+            }
+        }
+        entries.index_remove(&sessions_key(user_id), &[session_id]);
+        entries.index_remove(LIVE_SESSIONS_KEY, &[session_id]);
+        Ok(deleted)
+    }
+
+    async fn delete_all_sessions(&self, user_id: Uuid) -> StoreResult<usize> {
+        let mut entries = self.entries();
+        let now = Instant::now();
+        let mut deleted = 0;
+        let session_ids: Vec<Uuid> = entries
+            .take_index(&sessions_key(user_id))
+            .into_keys()
+            .collect();
+        for session_id in &session_ids {
+            for token_uuid in entries
+                .take_index(&session_tokens_key(session_id))
+                .into_keys()
+            {
+                if let Some(entry) = entries.map.remove(&token_uuid.to_string()) {
+                    deleted += entry.live(now) as usize;
+                }
+            }
+        }
+        entries.index_remove(LIVE_SESSIONS_KEY, &session_ids);
+        Ok(deleted)
+    }
+
+    async fn count_sessions(&self) -> StoreResult<i64> {
+        Ok(self.entries().index_remove(LIVE_SESSIONS_KEY, &[]) as i64)
+    }
+
+    async fn use_once(&self, key: &str, ttl_secs: u64) -> StoreResult<bool> {
+        let mut entries = self.entries();
+        if entries.get(key).is_some() {
+            return Ok(false);
+        }
+        entries.insert(
+            key.to_string(),
+            Value::Text("1".to_string()),
+            Some(ttl_secs),
+        );
+        Ok(true)
+    }
+
+    async fn get_cached(&self, key: &str) -> StoreResult<Option<String>> {
+        Ok(match self.entries().get(key) {
+            Some(Value::Text(value)) => Some(value.clone()),
+            _ => None,
+        })
+    }
+
+    async fn put_cached(&self, key: &str, value: String, ttl_secs: u64) -> StoreResult<()> {
+        self.entries()
+            .insert(key.to_string(), Value::Text(value), Some(ttl_secs));
+        Ok(())
+    }
+
+    async fn delete_cached(&self, key: &str) -> StoreResult<()> {
+        self.entries().map.remove(key);
+        Ok(())
+    }
+
+    async fn counters(&self, keys: &[&str]) -> StoreResult<Vec<i64>> {
+        let entries = self.entries();
+        Ok(keys.iter().map(|key| entries.counter(key)).collect())
+    }
+
+    async fn reserve(&self, counters: &[CounterLimit<'_>], amount: i64) -> StoreResult<bool> {
+        let mut entries = self.entries();
+        let fits = counters.iter().all(|counter| {
+            counter
+                .limit
+                .is_none_or(|limit| entries.counter(counter.key) + amount <= limit)
+        });
+        if !fits {
+            return Ok(false);
+        }
+        for counter in counters {
+            let used = entries.counter(counter.key);
+            entries.insert(
+                counter.key.to_string(),
+                Value::Counter(used + amount),
+                Some(counter.ttl_secs),
+            );
+        }
+        Ok(true)
+    }
+
+    async fn add_to_counters(&self, keys: &[&str], delta: i64) -> StoreResult<()> {
+        let mut entries = self.entries();
+        for key in keys {
+            match entries.get_mut(key) {
+                Some(Entry {
+                    value: Value::Counter(n),
+                    ..
+                }) => *n += delta,
+                // Like INCRBY on a missing key, the counter has no expiry.
+                _ => entries.insert(key.to_string(), Value::Counter(delta), None),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn redis_store_backs_off_after_a_failed_connect() {
+        let client = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        let store = RedisSessionStore::new(client);
+        let error = store.ping().await.unwrap_err();
+        assert!(!error.to_string().contains("retrying"), "{}", error);
+
+        let started = Instant::now();
+        let error = store.ping().await.unwrap_err();
+        assert!(error.to_string().contains("retrying"), "{}", error);
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[actix_web::test]
+    async fn memory_store_tracks_sessions() {
+        let store = MemorySessionStore::new();
+        let user = Uuid::new_v4();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let (a, b, c, d) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        store.put_session(a, user, first, 60, 600).await.unwrap();
+        store.put_session(b, user, first, 60, 600).await.unwrap();
+        store.put_session(c, user, second, 60, 600).await.unwrap();
+        store.put_session(d, user, second, 60, 600).await.unwrap();
+        assert_eq!(store.get_session(a).await.unwrap(), Some(user));
+        assert_eq!(store.count_sessions().await.unwrap(), 2);
+
+        store.delete_sessions(&[a]).await.unwrap();
+        assert_eq!(store.get_session(a).await.unwrap(), None);
+        assert_eq!(store.end_session(user, first).await.unwrap(), 1);
+        assert_eq!(store.get_session(b).await.unwrap(), None);
+        assert_eq!(store.count_sessions().await.unwrap(), 1);
+
+        store.expire_session(c, 0).await.unwrap();
+        assert_eq!(store.get_session(c).await.unwrap(), None);
+        // Only `d` was still live.
+        assert_eq!(store.delete_all_sessions(user).await.unwrap(), 1);
+        assert_eq!(store.get_session(d).await.unwrap(), None);
+        assert_eq!(store.count_sessions().await.unwrap(), 0);
+        assert_eq!(store.delete_all_sessions(user).await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn memory_store_uses_keys_once() {
+        let store = MemorySessionStore::new();
+        assert!(store.use_once("nonce:n", 60).await.unwrap());
+        assert!(!store.use_once("nonce:n", 60).await.unwrap());
+        assert!(store.use_once("nonce:m", 60).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn memory_store_reserves_within_every_limit() {
+        let store = MemorySessionStore::new();
+        let counters = [
+            CounterLimit {
+                key: "day",
+                limit: Some(10),
+                ttl_secs: 60,
+            },
+            CounterLimit {
+                key: "month",
+                limit: None,
+                ttl_secs: 60,
+            },
+        ];
+        assert!(store.reserve(&counters, 8).await.unwrap());
+        assert!(!store.reserve(&counters, 3).await.unwrap());
+        assert_eq!(store.counters(&["day", "month"]).await.unwrap(), [8, 8]);
+
+        store.add_to_counters(&["day", "month"], -5).await.unwrap();
+        assert!(store.reserve(&counters, 3).await.unwrap());
+        assert_eq!(
+            store.counters(&["day", "month", "none"]).await.unwrap(),
+            [6, 6, 0]
+        );
+    }
+}
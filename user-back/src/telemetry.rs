@@ -0,0 +1,230 @@
+use std::future::Future;
+use std::time::Instant;
+
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    Error, HttpMessage,
+};
+use opentelemetry::{propagation::Extractor, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::{field::Empty, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use uuid::Uuid;
+
+use crate::config::{LogFormat, TelemetryConfig};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest incoming request id that is reused instead of replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Id of the current request, taken from `X-Request-Id` when nginx sent one.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Flushes buffered spans when dropped at shutdown.
+pub struct TelemetryGuard {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                tracing::error!(error = %e, "ошибка экспорта трейсов");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber: text or JSON logs filtered by `RUST_LOG`,
+/// plus OTLP export when a collector endpoint is configured.
+pub fn init(config: &TelemetryConfig) -> TelemetryGuard {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt = match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    // `TelemetryConfig` only keeps an endpoint that parses, but the exporter
+    // can still fail to build; logs then go out without traces.
+    let exporter = config.otlp_endpoint.as_ref().map(|endpoint| {
+        SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint))
+            .build()
+    });
+    let (exporter, export_error) = match exporter {
+        Some(Ok(exporter)) => (Some(exporter), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+    let provider = exporter.map(|exporter| {
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(config.service_name.clone())
+                    .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+                    .build(),
+            )
+            .build()
+    });
+    let otel = provider.as_ref().map(|provider| {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("user-back"))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(otel)
+        .init();
+    if let Some(e) = export_error {
+        tracing::error!(error = %e, "экспорт трейсов отключён");
+    }
+
+    TelemetryGuard { provider }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+fn incoming_request_id(req: &ServiceRequest) -> Option<String> {
+    let value = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+
+/// Opens the span for a request. `user_id` and `session_id` are filled in by
+/// `JwtMiddleware`; route, status and outcome once the response is ready.
+pub fn request_span(req: &ServiceRequest) -> Span {
+    let request_id = incoming_request_id(req).unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = tracing::info_span!(
+        "http.request",
+        method = %req.method(),
+        path = %req.path(),
+        route = Empty,
+        request_id = %request_id,
+        user_id = Empty,
+        session_id = Empty,
+        status = Empty,
+        outcome = Empty,
+    );
+
+    // Continues the caller's trace when a `traceparent` header came in.
+    let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    let _ = span.set_parent(parent);
+
+    req.extensions_mut().insert(RequestId(request_id));
+    span
+}
+
+fn outcome(status: actix_web::http::StatusCode) -> &'static str {
+    if status.is_server_error() {
+        "server_error"
+    } else if status.is_client_error() {
+        "client_error"
+    } else {
+        "success"
+    }
+}
+
+/// Runs the rest of the chain inside `span`, logs the finished request and
+/// echoes the request id back so nginx and clients can correlate it.
+pub async fn trace<B, F>(span: Span, response: F) -> Result<ServiceResponse<B>, Error>
+where
+    F: Future<Output = Result<ServiceResponse<B>, Error>>,
+{
+    let started = Instant::now();
+    let mut response = match response.instrument(span.clone()).await {
+        Ok(response) => response,
+        Err(e) => {
+            span.record("outcome", "server_error");
+            span.in_scope(|| tracing::error!(error = %e, "запрос завершился ошибкой"));
+            return Err(e);
+        }
+    };
+
+    let status = response.status();
+    if let Some(route) = response.request().match_pattern() {
+        span.record("route", route.as_str());
+    }
+    span.record("status", status.as_u16());
+    span.record("outcome", outcome(status));
+    span.in_scope(|| {
+        tracing::info!(
+            latency_ms = started.elapsed().as_millis() as u64,
+            "запрос обработан"
+        )
+    });
+
+    let request_id = response
+        .request()
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| HeaderValue::from_str(&id.0).ok());
+    if let Some(request_id) = request_id {
+        response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{dev::Service, test, web, App, HttpResponse};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn reuses_or_assigns_request_id() {
+        let app = test::init_service(
+            App::new()
+                .route("/ping", web::get().to(HttpResponse::Ok))
+                .wrap_fn(|req, srv| {
+                    let span = request_span(&req);
+                    trace(span, srv.call(req))
+                }),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/ping")
+            .insert_header((REQUEST_ID_HEADER, "from-nginx-1"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "from-nginx-1"
+        );
+
+        let request = test::TestRequest::get()
+            .uri("/ping")
+            .insert_header((REQUEST_ID_HEADER, "has spaces"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let assigned = response.headers().get(REQUEST_ID_HEADER).unwrap();
+        assert!(Uuid::parse_str(assigned.to_str().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,433 @@
+use crate::{
+    error::{ApiError, ErrorEnvelope},
+    jwt_auth,
+    model::{CreateTemplateSchema, PromptTemplate, UpdateTemplateSchema},
+    AppState,
+};
+
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+use validator::Validate;
+
+pub const DEFAULT_LANGUAGE: &str = "tatar";
+
+/// Prompt used when no template applies; matches what `llm-back` used to
+/// build on its own.
+const FALLBACK_PROMPT_FORMAT: &str = "[INST] {message} [/INST]";
+
+/// Sampling parameters sent upstream, after request overrides and template
+/// defaults have been applied.
+#[derive(Debug, Clone, Copy)]
+pub struct GenerationParams {
+    pub max_new_tokens: i64,
+    pub temperature: Option<f32>,
+}
+
+impl GenerationParams {
+    pub fn resolve(
+        template: Option<&PromptTemplate>,
+        max_new_tokens: Option<i64>,
+        temperature: Option<f32>,
+        default_max_new_tokens: i64,
+    ) -> GenerationParams {
+        GenerationParams {
+            max_new_tokens: max_new_tokens
+                .or_else(|| template.and_then(|t| t.max_new_tokens.map(i64::from)))
+                .unwrap_or(default_max_new_tokens)
+                .max(1),
+            temperature: temperature.or_else(|| template.and_then(|t| t.temperature)),
+        }
+    }
+}
+
+/// Replaces `{name}` placeholders in a single pass, so placeholders inside
+/// substituted user text are left alone.
+fn fill(format: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(format.len());
+    let mut rest = format;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &after[..end])
+                .map(|(_, value)| (end, *value))
+        });
+
+        match value {
+            Some((end, value)) => {
+                rendered.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// The template's system prompt with persona and language filled in.
+pub fn system_prompt(template: &PromptTemplate) -> String {
+    fill(
+        &template.system_prompt,
+        &[
+            ("persona", &template.persona),
+            ("language", &template.language),
+        ],
+    )
+}
+
+/// Builds the raw prompt for the LLM from the template, previous
+/// `(user, assistant)` turns and the new user message.
+pub fn render(
+    template: Option<&PromptTemplate>,
+    history: &[(String, String)],
+    message: &str,
+) -> String {
+    let template = match template {
+        Some(template) => template,
+        None => return fill(FALLBACK_PROMPT_FORMAT, &[("message", message)]),
+    };
+
+    let system = system_prompt(template);
+    let history: String = history
+        .iter()
+        .map(|(user, assistant)| {
+            fill(
+                &template.history_format,
+                &[("user", user), ("assistant", assistant)],
+            )
+        })
+        .collect();
+
+    fill(
+        &template.prompt_format,
+        &[
+            ("system", &system),
+            ("persona", &template.persona),
+            ("language", &template.language),
+            ("history", &history),
+            ("message", message),
+        ],
+    )
+}
+
+pub async fn find_active(
+    db: &Pool<Postgres>,
+    template_id: Uuid,
+) -> Result<Option<PromptTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        PromptTemplate,
+        "SELECT id, name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, active, created_at, updated_at FROM prompt_templates WHERE id = $1 AND active",
+        template_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// The template whether or not it is still active, for conversations that
+/// were started with it.
+pub async fn find(
+    db: &Pool<Postgres>,
+    template_id: Uuid,
+) -> Result<Option<PromptTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        PromptTemplate,
+        "SELECT id, name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, active, created_at, updated_at FROM prompt_templates WHERE id = $1",
+        template_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn find_default(
+    db: &Pool<Postgres>,
+    language: &str,
+) -> Result<Option<PromptTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        PromptTemplate,
+        "SELECT id, name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, active, created_at, updated_at FROM prompt_templates WHERE language = $1 AND is_default AND active",
+        language.to_lowercase()
+    )
+    .fetch_optional(db)
+    .await
+}
+
+#[utoipa::path(
+    tag = "templates",
+    responses(
+        (status = 200, description = "`data.templates`: id, name, language, persona and is_default of active templates", body = Object),
+    )
+)]
+#[get("/templates")]
+async fn list_templates_handler(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let templates = sqlx::query_as!(
+        PromptTemplate,
+        "SELECT id, name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, active, created_at, updated_at FROM prompt_templates WHERE active ORDER BY language, name"
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let templates: Vec<serde_json::Value> = templates
+        .iter()
+        .map(|template| {
+            serde_json::json!({
+                "id": template.id,
+                "name": template.name,
+                "language": template.language,
+                "persona": template.persona,
+                "is_default": template.is_default
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"templates": templates}
+    })))
+}
+
+#[utoipa::path(
+    tag = "templates",
+    responses(
+        (status = 200, description = "`data.templates`: all `PromptTemplate`s", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[get("/admins/templates")]
+async fn admin_list_templates_handler(
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    let templates = sqlx::query_as!(
+        PromptTemplate,
+        "SELECT id, name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, active, created_at, updated_at FROM prompt_templates ORDER BY language, name"
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"templates": templates}
+    })))
+}
+
+#[utoipa::path(
+    tag = "templates",
+    responses(
+        (status = 201, description = "`data.template`: the created `PromptTemplate`", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+        (status = 409, description = "Template name already taken", body = ErrorEnvelope),
+        (status = 422, description = "Request validation failed", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[post("/admins/templates")]
+async fn create_template_handler(
+    body: web::Json<CreateTemplateSchema>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    body.validate()?;
+
+    let language = body
+        .language
+        .as_deref()
+        .unwrap_or(DEFAULT_LANGUAGE)
+        .to_lowercase();
+    let is_default = body.is_default.unwrap_or(false);
+
+    let mut tx = data.db.begin().await?;
+
+    if is_default {
+        sqlx::query!(
+            "UPDATE prompt_templates SET is_default = FALSE, updated_at = NOW() WHERE language = $1 AND is_default",
+            language
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let template = sqlx::query_as!(
+        PromptTemplate,
+        "INSERT INTO prompt_templates (name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, created_by)
+         VALUES ($1, $2, COALESCE($3, ''), COALESCE($4, ''), COALESCE($5, E'{history}[INST] {system}\\n\\n{message} [/INST]'), COALESCE($6, '[INST] {user} [/INST] {assistant}</s>'), $7, $8, $9, $10)
+         RETURNING id, name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, active, created_at, updated_at",
+        body.name.trim(),
+        language,
+        body.persona,
+        body.system_prompt,
+        body.prompt_format,
+        body.history_format,
+        body.temperature,
+        body.max_new_tokens,
+        is_default,
+        jwt.user.id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => {
+            ApiError::Conflict("Template with that name already exists".to_string())
+        }
+        e => e.into(),
+    })?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "status": "success",
+        "data": {"template": template}
+    })))
+}
+
+#[utoipa::path(
+    tag = "templates",
+    params(
+        ("id" = Uuid, Path, description = "Template id"),
+    ),
+    responses(
+        (status = 200, description = "`data.template`: the updated `PromptTemplate`", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+        (status = 404, description = "Template not found", body = ErrorEnvelope),
+        (status = 409, description = "Template name or default already taken", body = ErrorEnvelope),
+        (status = 422, description = "Request validation failed", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[put("/admins/templates/{id}")]
+async fn update_template_handler(
+    path: web::Path<Uuid>,
+    body: web::Json<UpdateTemplateSchema>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    body.validate()?;
+
+    let template_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    if body.is_default == Some(true) {
+        sqlx::query!(
+            "UPDATE prompt_templates SET is_default = FALSE, updated_at = NOW()
+             WHERE is_default AND id <> $1
+               AND language = COALESCE($2, (SELECT language FROM prompt_templates WHERE id = $1))",
+            template_id,
+            body.language
+                .as_ref()
+                .map(|language| language.to_lowercase())
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let template = sqlx::query_as!(
+        PromptTemplate,
+        "UPDATE prompt_templates SET
+            name = COALESCE($2, name),
+            language = COALESCE($3, language),
+            persona = COALESCE($4, persona),
+            system_prompt = COALESCE($5, system_prompt),
+            prompt_format = COALESCE($6, prompt_format),
+            history_format = COALESCE($7, history_format),
+            temperature = COALESCE($8, temperature),
+            max_new_tokens = COALESCE($9, max_new_tokens),
+            is_default = COALESCE($10, is_default) AND COALESCE($11, active),
+            active = COALESCE($11, active),
+            updated_at = NOW()
+         WHERE id = $1
+         RETURNING id, name, language, persona, system_prompt, prompt_format, history_format, temperature, max_new_tokens, is_default, active, created_at, updated_at",
+        template_id,
+        body.name,
+        body.language.as_ref().map(|language| language.to_lowercase()),
+        body.persona,
+        body.system_prompt,
+        body.prompt_format,
+        body.history_format,
+        body.temperature,
+        body.max_new_tokens,
+        body.is_default,
+        body.active
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => ApiError::Conflict(
+            "Template name or default language is already taken".to_string(),
+        ),
+        e => e.into(),
+    })?
+    .ok_or(ApiError::NotFound("Template"))?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "data": {"template": template}
+    })))
+}
+
+/// Templates are only deactivated so conversations and messages keep
+/// pointing at the template they were rendered with.
+#[utoipa::path(
+    tag = "templates",
+    params(
+        ("id" = Uuid, Path, description = "Template id"),
+    ),
+    responses(
+        (status = 200, description = "Template deactivated", body = Object),
+        (status = 401, description = "Missing, invalid or expired access token", body = ErrorEnvelope),
+        (status = 403, description = "Admin privileges required", body = ErrorEnvelope),
+        (status = 404, description = "Template not found", body = ErrorEnvelope),
+    ),
+    security(("bearer_auth" = []), ("cookie_auth" = []))
+)]
+#[delete("/admins/templates/{id}")]
+async fn deactivate_template_handler(
+    path: web::Path<Uuid>,
+    jwt: jwt_auth::JwtMiddleware,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    jwt.require_admin()?;
+
+    let result = sqlx::query!(
+        "UPDATE prompt_templates SET active = FALSE, is_default = FALSE, updated_at = NOW() WHERE id = $1",
+        path.into_inner()
+    )
+    .execute(&data.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Template"));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success"
+    })))
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    conf.service(list_templates_handler)
+        .service(admin_list_templates_handler)
+        .service(create_template_handler)
+        .service(update_template_handler)
+        .service(deactivate_template_handler);
+}
@@ -1,10 +1,14 @@
 use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::config::Config;
+use crate::error::ApiError;
+use crate::metrics;
+use crate::model::UserProfile;
+use crate::profile;
+use crate::session_store::{SessionStore, StoreError, StoreResult};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenDetails {
@@ -12,23 +16,127 @@ pub struct TokenDetails {
     pub token_uuid: Uuid,
     pub user_id: Uuid,
     pub expires_in: i64,
+    /// Thumbprint of the DPoP key the token is bound to, if any.
+    pub key_thumbprint: Option<String>,
+    /// `None` for tokens issued before sessions had ids.
+    pub session_id: Option<Uuid>,
+    pub profile: Option<ProfileClaims>,
+}
+
+/// Whom a token is issued to. A login starts a session that refreshed and
+/// reissued tokens keep.
+pub struct TokenSubject<'a> {
+    pub user_id: Uuid,
+    pub session_id: Uuid,
+    pub key_thumbprint: Option<&'a str>,
+    /// Embedded in access tokens so hot endpoints need not load the user.
+    pub profile: Option<&'a UserProfile>,
+}
+
+/// What an access token says about its user, as of when it was issued.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileClaims {
+    pub role: String,
+    pub permissions: Vec<String>,
+    pub verified: bool,
+    pub locale: Option<String>,
+}
+
+/// RFC 7800 confirmation claim of a token bound to a DPoP key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Confirmation {
+    pub jkt: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
-    pub sub: String, 
-    pub exp: i64,       
-    pub iat: i64,  
-    pub jti: String,    
-    pub iss: String,   
-    pub aud: String,   
-    pub typ: String,   
-    pub nonce: String, 
-    pub nbf: i64,    
+    pub sub: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub jti: String,
+    pub iss: String,
+    pub aud: String,
+    pub typ: String,
+    pub nonce: String,
+    pub nbf: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
 }
 
-pub fn generate_jwt_token(
+/// Records a freshly issued token as a live session of its user. The index
+/// lives as long as a refresh token, the longest-lived id it can hold.
+/// Tokens from before sessions had ids form a session of their own.
+pub async fn store_session(
+    store: &dyn SessionStore,
+    details: &TokenDetails,
+    max_age: i64,
+    config: &Config,
+) -> StoreResult<()> {
+    store
+        .put_session(
+            details.token_uuid,
+            details.user_id,
+            details.session_id.unwrap_or(details.token_uuid),
+            (max_age * 60) as u64,
+            (config.refresh_token_max_age * 60) as u64,
+        )
+        .await
+}
+
+pub async fn revoke_sessions(store: &dyn SessionStore, token_uuids: &[Uuid]) -> StoreResult<()> {
+    store.delete_sessions(token_uuids).await
+}
+
+/// Claims the right to replace an access token that is close to expiry.
+/// Only the first of several concurrent requests with the same token gets
+/// `true`, so a burst of requests mints a single successor.
+pub async fn claim_reissue(
+    store: &dyn SessionStore,
+    token_uuid: Uuid,
+    grace_secs: u64,
+) -> StoreResult<bool> {
+    store
+        .use_once(&format!("reissued:{}", token_uuid), grace_secs)
+        .await
+}
+
+/// Lets a replaced access token run out after `grace_secs` instead of at its
+/// own expiry. It stays in the sessions index until then.
+pub async fn retire_session(
+    store: &dyn SessionStore,
+    token_uuid: Uuid,
+    grace_secs: u64,
+) -> StoreResult<()> {
+    store.expire_session(token_uuid, grace_secs).await
+}
+
+/// Drops every access and refresh token of one login of the user.
+pub async fn end_session(
+    store: &dyn SessionStore,
     user_id: Uuid,
+    session_id: Uuid,
+) -> StoreResult<usize> {
+    store.end_session(user_id, session_id).await
+}
+
+/// Drops every access and refresh token of the user and returns how many
+/// were still live.
+pub async fn revoke_all_sessions(store: &dyn SessionStore, user_id: Uuid) -> StoreResult<usize> {
+    store.delete_all_sessions(user_id).await
+}
+
+pub fn generate_jwt_token(
+    subject: &TokenSubject,
     max_age: i64,
     private_key: String,
     token_type: &str,
@@ -37,17 +145,29 @@ pub fn generate_jwt_token(
     let now = Utc::now();
     let expires_in = now + Duration::minutes(max_age);
     let token_uuid = Uuid::new_v4();
+    let profile = subject.profile.map(ProfileClaims::from);
 
     let claims = TokenClaims {
-        sub: user_id.to_string(),
+        sub: subject.user_id.to_string(),
         exp: expires_in.timestamp(),
         iat: now.timestamp(),
         jti: token_uuid.to_string(),
         iss: config.token_issuer.clone(),
         aud: config.token_audience.clone(),
         typ: token_type.to_string(),
-        nonce: Uuid::new_v4().to_string(), 
-        nbf: now.timestamp(),              
+        nonce: Uuid::new_v4().to_string(),
+        nbf: now.timestamp(),
+        cnf: subject.key_thumbprint.map(|jkt| Confirmation {
+            jkt: jkt.to_string(),
+        }),
+        sid: Some(subject.session_id.to_string()),
+        role: profile.as_ref().map(|p| p.role.clone()),
+        permissions: profile
+            .as_ref()
+            .map(|p| p.permissions.clone())
+            .unwrap_or_default(),
+        verified: profile.as_ref().map(|p| p.verified),
+        locale: profile.as_ref().and_then(|p| p.locale.clone()),
     };
 
     let header = Header::new(Algorithm::RS256);
@@ -56,24 +176,104 @@ pub fn generate_jwt_token(
         &claims,
         &EncodingKey::from_rsa_pem(private_key.as_bytes())?,
     )?;
+    metrics::TOKENS_ISSUED
+        .with_label_values(&[token_type])
+        .inc();
 
     Ok(TokenDetails {
         token: Some(token),
         token_uuid,
-        user_id,
+        user_id: subject.user_id,
         expires_in: expires_in.timestamp(),
+        key_thumbprint: subject.key_thumbprint.map(str::to_string),
+        session_id: Some(subject.session_id),
+        profile,
     })
 }
 
+impl From<&UserProfile> for ProfileClaims {
+    fn from(user: &UserProfile) -> Self {
+        ProfileClaims {
+            role: user.role.clone(),
+            permissions: profile::permissions(&user.role),
+            verified: user.verified,
+            locale: user.locale.clone(),
+        }
+    }
+}
+
+impl TokenClaims {
+    /// The profile claims, when the token was issued with them.
+    fn profile(&self) -> Option<ProfileClaims> {
+        Some(ProfileClaims {
+            role: self.role.clone()?,
+            permissions: self.permissions.clone(),
+            verified: self.verified?,
+            locale: self.locale.clone(),
+        })
+    }
+}
+
+/// Why a token was not accepted: it is invalid, or the session store could
+/// not be asked whether it was already used.
+#[derive(Debug)]
+pub enum VerifyError {
+    Invalid(jsonwebtoken::errors::Error),
+    Store(StoreError),
+}
+
+impl VerifyError {
+    /// `invalid` builds the error for a rejected token; a store failure is
+    /// reported as such, so an outage does not end sessions.
+    pub fn into_api_error(
+        self,
+        invalid: impl FnOnce(jsonwebtoken::errors::Error) -> ApiError,
+    ) -> ApiError {
+        match self {
+            VerifyError::Invalid(e) => invalid(e),
+            VerifyError::Store(e) => ApiError::Store(e),
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for VerifyError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        VerifyError::Invalid(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::ErrorKind> for VerifyError {
+    fn from(kind: jsonwebtoken::errors::ErrorKind) -> Self {
+        VerifyError::Invalid(kind.into())
+    }
+}
+
 pub async fn verify_jwt_token(
     public_key: String,
     token: &str,
     config: &Config,
-    redis_client: &mut redis::aio::Connection,
-) -> Result<TokenDetails, jsonwebtoken::errors::Error> {
+    store: &dyn SessionStore,
+) -> Result<TokenDetails, VerifyError> {
+    let mut typ = "unknown";
+    let result = verify(public_key, token, config, store, &mut typ).await;
+    metrics::TOKENS_VERIFIED
+        .with_label_values(&[typ, if result.is_ok() { "valid" } else { "invalid" }])
+        .inc();
+    result
+}
+
+/// Sets `typ` as soon as the claims decode, so failures later on are still
+/// counted against the right token type.
+async fn verify(
+    public_key: String,
+    token: &str,
+    config: &Config,
+    store: &dyn SessionStore,
+    typ: &mut &'static str,
+) -> Result<TokenDetails, VerifyError> {
     let mut validation = Validation::new(Algorithm::RS256);
     validation.validate_exp = true;
-    validation.validate_nbf = true; 
+    validation.validate_nbf = true;
     validation.set_issuer(&[config.token_issuer.as_str()]);
     validation.set_audience(&[config.token_audience.as_str()]);
     validation.leeway = 60;
@@ -81,39 +281,91 @@ pub async fn verify_jwt_token(
     let decoding_key = DecodingKey::from_rsa_pem(public_key.as_bytes())?;
     let decoded = decode::<TokenClaims>(token, &decoding_key, &validation)?;
 
-    if decoded.claims.typ != "access" && decoded.claims.typ != "refresh" {
-        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
-    }
+    *typ = match decoded.claims.typ.as_str() {
+        "access" => "access",
+        "refresh" => "refresh",
+        _ => return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into()),
+    };
 
-    let nonce_key = format!("nonce:{}", decoded.claims.nonce);
-    if redis_client
-        .exists(&nonce_key)
-        .await
-        .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidToken)?
-    {
-        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    // Access tokens stay reusable until expiry, like any bearer token; the
+    // endpoints that need more check a DPoP proof. Only refresh tokens are
+    // held to a single use.
+    if *typ == "refresh" {
+        let token_lifetime = (decoded.claims.exp - decoded.claims.iat) as u64;
+        let first_use = store
+            .use_once(
+                &format!("nonce:{}", decoded.claims.nonce),
+                token_lifetime + 300,
+            )
+            .await
+            .map_err(VerifyError::Store)?;
+        if !first_use {
+            return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+        }
     }
 
-    let token_lifetime = (decoded.claims.exp - decoded.claims.iat) as usize;
-    redis_client
-        .set_ex(
-            &nonce_key,
-            "used",
-            token_lifetime + 300,
-        )
-        .await
-        .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidToken)?;
-
     let user_id = Uuid::parse_str(&decoded.claims.sub)
         .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidSubject)?;
 
     let token_uuid = Uuid::parse_str(&decoded.claims.jti)
         .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidToken)?;
 
+    let session_id = decoded
+        .claims
+        .sid
+        .as_deref()
+        .and_then(|sid| Uuid::parse_str(sid).ok());
+
     Ok(TokenDetails {
         token: None,
         token_uuid,
         user_id,
         expires_in: decoded.claims.exp,
+        key_thumbprint: decoded.claims.cnf.as_ref().map(|cnf| cnf.jkt.clone()),
+        session_id,
+        profile: decoded.claims.profile(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(extra: serde_json::Value) -> TokenClaims {
+        let mut claims = serde_json::json!({
+            "sub": Uuid::new_v4().to_string(),
+            "exp": 2, "iat": 1, "nbf": 1,
+            "jti": Uuid::new_v4().to_string(),
+            "iss": "secure-app", "aud": "secure-app-users",
+            "typ": "access", "nonce": "n"
+        });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(claims).unwrap()
+    }
+
+    #[test]
+    fn profile_claims_are_optional() {
+        let old = claims(serde_json::json!({}));
+        assert!(old.profile().is_none());
+        assert!(old.sid.is_none());
+
+        let admin = claims(serde_json::json!({
+            "sid": Uuid::new_v4().to_string(),
+            "role": "admin",
+            "permissions": profile::permissions("admin"),
+            "verified": true
+        }));
+        let profile = admin.profile().unwrap();
+        assert_eq!(profile.role, "admin");
+        assert!(profile.permissions.iter().any(|p| p == "feedback:export"));
+        assert!(profile.verified);
+        assert_eq!(profile.locale, None);
+
+        // Profile claims are left out of tokens that do not carry them.
+        let json = serde_json::to_value(&old).unwrap();
+        assert!(json.get("role").is_none() && json.get("permissions").is_none());
+    }
+}
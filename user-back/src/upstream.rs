@@ -0,0 +1,414 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::config::UpstreamConfig;
+
+const PROBE_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Debug)]
+pub enum UpstreamError {
+    /// Every upstream in the pool is unhealthy or has an open circuit.
+    Unavailable,
+    Request(reqwest::Error),
+    Status(reqwest::StatusCode),
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamError::Unavailable => write!(f, "no upstream available"),
+            UpstreamError::Request(e) => write!(f, "{}", e),
+            UpstreamError::Status(status) => write!(f, "upstream responded with {}", status),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct UpstreamState {
+    healthy: bool,
+    consecutive_failures: u32,
+    circuit_open_until: Option<Instant>,
+    last_checked_at: Option<DateTime<Utc>>,
+    last_latency_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct Upstream {
+    url: String,
+    weight: u32,
+    state: Mutex<UpstreamState>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpstreamStatus {
+    pub url: String,
+    pub weight: u32,
+    pub healthy: bool,
+    pub circuit_open: bool,
+    pub consecutive_failures: u32,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    pub last_latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// A weighted set of interchangeable upstreams with per-upstream circuit
+/// breaking. Requests fail over to the next candidate on connection errors
+/// and 5xx responses.
+#[derive(Debug)]
+pub struct UpstreamPool {
+    name: &'static str,
+    probe_paths: Vec<String>,
+    failure_threshold: u32,
+    cooldown: Duration,
+    upstreams: Vec<Upstream>,
+}
+
+impl UpstreamPool {
+    pub fn new(
+        name: &'static str,
+        upstreams: &[UpstreamConfig],
+        probe_paths: &[String],
+        failure_threshold: u32,
+        cooldown_secs: u64,
+    ) -> UpstreamPool {
+        UpstreamPool {
+            name,
+            probe_paths: probe_paths.to_vec(),
+            failure_threshold: failure_threshold.max(1),
+            cooldown: Duration::from_secs(cooldown_secs),
+            upstreams: upstreams
+                .iter()
+                .map(|upstream| Upstream {
+                    url: upstream.url.clone(),
+                    weight: upstream.weight,
+                    // Optimistic until the first probe says otherwise.
+                    state: Mutex::new(UpstreamState {
+                        healthy: true,
+                        ..Default::default()
+                    }),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn is_available(&self, upstream: &Upstream) -> bool {
+        let state = upstream.state.lock().unwrap();
+        match state.circuit_open_until {
+            // Half-open: once the cooldown has passed, let a request through.
+            Some(until) => Instant::now() >= until,
+            None => state.healthy,
+        }
+    }
+
+    /// Available upstreams in weighted random order.
+    fn candidates(&self) -> Vec<&Upstream> {
+        let mut remaining: Vec<&Upstream> = self
+            .upstreams
+            .iter()
+            .filter(|upstream| upstream.weight > 0 && self.is_available(upstream))
+            .collect();
+        let mut ordered = Vec::with_capacity(remaining.len());
+        let mut rng = rand::thread_rng();
+
+        while !remaining.is_empty() {
+            let total: u32 = remaining.iter().map(|upstream| upstream.weight).sum();
+            let mut pick = rng.gen_range(0..total);
+            let index = remaining
+                .iter()
+                .position(|upstream| {
+                    if pick < upstream.weight {
+                        true
+                    } else {
+                        pick -= upstream.weight;
+                        false
+                    }
+                })
+                .unwrap_or(0);
+            ordered.push(remaining.remove(index));
+        }
+
+        ordered
+    }
+
+    fn record_success(&self, upstream: &Upstream, latency: Duration) {
+        let mut state = upstream.state.lock().unwrap();
+        state.healthy = true;
+        state.consecutive_failures = 0;
+        state.circuit_open_until = None;
+        state.last_checked_at = Some(Utc::now());
+        state.last_latency_ms = Some(latency.as_millis() as u64);
+        state.last_error = None;
+    }
+
+    fn record_failure(&self, upstream: &Upstream, error: String) {
+        let mut state = upstream.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        state.last_checked_at = Some(Utc::now());
+        state.last_error = Some(error);
+
+        if state.consecutive_failures >= self.failure_threshold {
+            if state.circuit_open_until.is_none() {
+                tracing::warn!(
+                    pool = self.name,
+                    upstream = %upstream.url,
+                    failures = state.consecutive_failures,
+                    "⚠️ upstream отключён"
+                );
+            }
+            state.healthy = false;
+            state.circuit_open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Sends the request built by `build` to the pool, trying candidates in
+    /// order until one answers with a non-5xx status. Only connection errors
+    /// move on to the next candidate: after any other error, such as a read
+    /// timeout, the upstream may already be acting on the request.
+    pub async fn send<F>(
+        &self,
+        client: &reqwest::Client,
+        build: F,
+    ) -> Result<reqwest::Response, UpstreamError>
+    where
+        F: Fn(&reqwest::Client, &str) -> reqwest::RequestBuilder,
+    {
+        let mut last_error = UpstreamError::Unavailable;
+
+        for upstream in self.candidates() {
+            let started = Instant::now();
+            match build(client, &upstream.url).send().await {
+                Ok(response) if response.status().is_server_error() => {
+                    self.record_failure(upstream, format!("status {}", response.status()));
+                    last_error = UpstreamError::Status(response.status());
+                }
+                Ok(response) => {
+                    self.record_success(upstream, started.elapsed());
+                    return Ok(response);
+                }
+                Err(e) if e.is_connect() => {
+                    self.record_failure(upstream, e.to_string());
+                    last_error = UpstreamError::Request(e);
+                }
+                Err(e) => {
+                    self.record_failure(upstream, e.to_string());
+                    return Err(UpstreamError::Request(e));
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Probes every upstream once, including those with an open circuit, so a
+    /// recovered upstream rejoins the pool without waiting for live traffic.
+    /// An upstream passes when every probe path answers with a 2xx.
+    pub async fn probe(&self, client: &reqwest::Client) {
+        for upstream in &self.upstreams {
+            let started = Instant::now();
+            let mut result = Ok(());
+            for path in &self.probe_paths {
+                result = client
+                    .get(format!("{}{}", upstream.url, path))
+                    .timeout(Duration::from_secs(PROBE_TIMEOUT_SECS))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map(|_| ())
+                    .map_err(|e| format!("{}: {}", path, e));
+                if result.is_err() {
+                    break;
+                }
+            }
+
+            match result {
+                Ok(()) => self.record_success(upstream, started.elapsed()),
+                Err(e) => self.record_failure(upstream, e),
+            }
+        }
+    }
+
+    pub fn has_available(&self) -> bool {
+        self.upstreams
+            .iter()
+            .any(|upstream| upstream.weight > 0 && self.is_available(upstream))
+    }
+
+    pub fn status(&self) -> Vec<UpstreamStatus> {
+        self.upstreams
+            .iter()
+            .map(|upstream| {
+                let state = upstream.state.lock().unwrap();
+                UpstreamStatus {
+                    url: upstream.url.clone(),
+                    weight: upstream.weight,
+                    healthy: state.healthy,
+                    circuit_open: state
+                        .circuit_open_until
+                        .is_some_and(|until| Instant::now() < until),
+                    consecutive_failures: state.consecutive_failures,
+                    last_checked_at: state.last_checked_at,
+                    last_latency_ms: state.last_latency_ms,
+                    last_error: state.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Periodically probes all pools for the lifetime of the server.
+pub async fn run_probes(
+    pools: Vec<Arc<UpstreamPool>>,
+    client: reqwest::Client,
+    interval_secs: u64,
+) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        for pool in &pools {
+            pool.probe(&client).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn pool(upstreams: &[(&str, u32)], cooldown_secs: u64) -> UpstreamPool {
+        let upstreams: Vec<UpstreamConfig> = upstreams
+            .iter()
+            .map(|(url, weight)| UpstreamConfig {
+                url: url.to_string(),
+                weight: *weight,
+            })
+            .collect();
+        UpstreamPool::new("test", &upstreams, &["/".to_string()], 2, cooldown_secs)
+    }
+
+    /// Answers every request with `status_line` from a background thread.
+    fn serve(status_line: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 4096]);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status_line
+                );
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn orders_candidates_by_weight() {
+        let pool = pool(&[("http://a", 3), ("http://b", 1), ("http://off", 0)], 30);
+        let mut a_first = 0;
+        for _ in 0..4000 {
+            let candidates = pool.candidates();
+            let urls: Vec<&str> = candidates.iter().map(|u| u.url.as_str()).collect();
+            assert_eq!(urls.len(), 2, "weight 0 is never a candidate");
+            if urls[0] == "http://a" {
+                a_first += 1;
+            }
+        }
+        // Expected 3000; the bounds are about ten standard deviations out.
+        assert!(
+            (2700..=3300).contains(&a_first),
+            "a first {} times",
+            a_first
+        );
+    }
+
+    #[test]
+    fn opens_the_circuit_after_repeated_failures() {
+        let pool = pool(&[("http://a", 1), ("http://b", 1)], 30);
+        let a = &pool.upstreams[0];
+
+        pool.record_failure(a, "boom".to_string());
+        assert!(pool.is_available(a), "below the threshold");
+
+        pool.record_failure(a, "boom".to_string());
+        assert!(!pool.is_available(a));
+        let candidates = pool.candidates();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].url, "http://b");
+        assert!(pool.status()[0].circuit_open);
+
+        pool.record_failure(&pool.upstreams[1], "boom".to_string());
+        pool.record_failure(&pool.upstreams[1], "boom".to_string());
+        assert!(!pool.has_available());
+    }
+
+    #[test]
+    fn half_opens_after_the_cooldown() {
+        let pool = pool(&[("http://a", 1)], 0);
+        let a = &pool.upstreams[0];
+        pool.record_failure(a, "boom".to_string());
+        pool.record_failure(a, "boom".to_string());
+        assert!(!pool.status()[0].healthy);
+        assert!(pool.is_available(a), "one request may test the upstream");
+
+        pool.record_success(a, Duration::from_millis(5));
+        let status = &pool.status()[0];
+        assert!(status.healthy && !status.circuit_open);
+        assert_eq!(status.consecutive_failures, 0);
+    }
+
+    #[actix_web::test]
+    async fn fails_over_on_connection_errors_and_5xx() {
+        let refused = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let failing = serve("503 Service Unavailable");
+        let ok = serve("200 OK");
+        let pool = pool(&[(&refused, 1), (&failing, 1), (&ok, 1)], 30);
+
+        let response = pool
+            .send(&reqwest::Client::new(), |client, url| client.get(url))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn does_not_fail_over_after_a_timeout() {
+        // Accepts connections but never answers.
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let silent_url = format!("http://{}", silent.local_addr().unwrap());
+        let ok = serve("200 OK");
+        let pool = pool(&[(&silent_url, 1), (&ok, 1)], 30);
+
+        for _ in 0..10 {
+            let tried = Mutex::new(Vec::new());
+            let result = pool
+                .send(&reqwest::Client::new(), |client, url| {
+                    tried.lock().unwrap().push(url.to_string());
+                    client.get(url).timeout(Duration::from_millis(100))
+                })
+                .await;
+            let tried = tried.into_inner().unwrap();
+            if tried[0] == silent_url {
+                assert!(matches!(result, Err(UpstreamError::Request(e)) if e.is_timeout()));
+                assert_eq!(tried.len(), 1, "the healthy upstream must not get a retry");
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::{
+    config::PasswordPolicy,
+    i18n::{self, Locale},
+};
+
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank"));
+    }
+    Ok(())
+}
+
+pub fn non_zero(value: i64) -> Result<(), ValidationError> {
+    if value == 0 {
+        return Err(ValidationError::new("zero"));
+    }
+    Ok(())
+}
+
+/// Checks a new password against the policy from `Config`.
+pub fn password_strength(value: &str, policy: &PasswordPolicy) -> Result<(), ValidationError> {
+    if value.chars().count() < policy.min_length {
+        let mut error = ValidationError::new("length");
+        error.add_param("min".into(), &policy.min_length);
+        return Err(error);
+    }
+    if policy.require_digit && !value.chars().any(|c| c.is_ascii_digit()) {
+        return Err(ValidationError::new("digit"));
+    }
+    if policy.require_uppercase && !value.chars().any(char::is_uppercase) {
+        return Err(ValidationError::new("uppercase"));
+    }
+    if policy.require_symbol && value.chars().all(char::is_alphanumeric) {
+        return Err(ValidationError::new("symbol"));
+    }
+    Ok(())
+}
+
+/// Message for one failed rule, built from its code and `min`/`max` params.
+fn message(error: &ValidationError, locale: Locale) -> String {
+    let param = |name: &str| error.params.get(name).map(|value| value.to_string());
+    let (min, max) = (param("min"), param("max"));
+
+    let key = match (error.code.as_ref(), &min, &max) {
+        ("length", Some(_), Some(_)) => "validation.length_between",
+        ("length", Some(_), None) => "validation.length_min",
+        ("length", None, Some(_)) => "validation.length_max",
+        ("range", Some(_), Some(_)) => "validation.range_between",
+        ("range", Some(_), None) => "validation.range_min",
+        ("range", None, Some(_)) => "validation.range_max",
+        ("blank", _, _) => "validation.blank",
+        ("zero", _, _) => "validation.zero",
+        ("digit", _, _) => "validation.digit",
+        ("uppercase", _, _) => "validation.uppercase",
+        ("symbol", _, _) => "validation.symbol",
+        ("email", _, _) => "validation.email",
+        _ => "validation.invalid",
+    };
+
+    i18n::text(locale, key)
+        .replace("{min}", min.as_deref().unwrap_or_default())
+        .replace("{max}", max.as_deref().unwrap_or_default())
+}
+
+/// Flattens validator output into `field -> messages`, using dotted paths for
+/// nested structs and `field[i]` for list items.
+pub fn field_messages(errors: &ValidationErrors, locale: Locale) -> BTreeMap<String, Vec<String>> {
+    let mut fields = BTreeMap::new();
+    collect(errors, "", locale, &mut fields);
+    fields
+}
+
+fn collect(
+    errors: &ValidationErrors,
+    prefix: &str,
+    locale: Locale,
+    fields: &mut BTreeMap<String, Vec<String>>,
+) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                fields
+                    .entry(path)
+                    .or_default()
+                    .extend(errors.iter().map(|error| message(error, locale)));
+            }
+            ValidationErrorsKind::Struct(errors) => collect(errors, &path, locale, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect(errors, &format!("{}[{}]", path, index), locale, fields);
+                }
+            }
+        }
+    }
+}